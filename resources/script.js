@@ -1,7 +1,7 @@
 function inputChanged() {
     var value = document.getElementById("id").value;
     if (value == "") {
-        setUrls("", "");
+        setUrls("", "", "");
         return;
     }
 
@@ -10,26 +10,28 @@ function inputChanged() {
     }
     catch(err) {
         var msg = "Invalid textual ID '" + value + "': " + err;
-        setUrls(msg, msg);
+        setUrls(msg, msg, "");
         return;
     }
 
-    // webcal://goout.net/services/feeder/usercalendar.ics?id=43224?...
-    var http_url = window.location.href + "services/feeder/usercalendar.ics?id=" + id;
-
     var language_value = document.getElementById("language").value;
-    http_url += "&language=" + language_value;
+    var params = "language=" + language_value;
 
     var after_value = document.getElementById("after").value;
     if (after_value) {
-        http_url += "&after=" + after_value;
+        params += "&after=" + after_value;
     }
 
     var longterm_value = document.getElementById("longTerm").value;
-    http_url += "&longterm=" + longterm_value;
+    params += "&longterm=" + longterm_value;
+
+    // webcal://goout.net/services/feeder/usercalendar.ics?id=43224&...
+    var http_url = window.location.href + "services/feeder/usercalendar.ics?id=" + id;
+    http_url += "&" + params + "&uids=v2";
 
     var webcal_url = http_url.replace(/^https?/, "webcal");
-    setUrls(http_url, webcal_url);
+    var agenda_url = window.location.href + "agenda/" + id + "?" + params;
+    setUrls(http_url, webcal_url, agenda_url);
 }
 
 function convertTextToId(value) {
@@ -47,7 +49,7 @@ function convertTextToId(value) {
     return id;
 }
 
-function setUrls(http_url, webcal_url) {
+function setUrls(http_url, webcal_url, agenda_url) {
     var entries = {
         http: http_url,
         webcal: webcal_url
@@ -62,4 +64,6 @@ function setUrls(http_url, webcal_url) {
         input_elem.value = url;
         a_elem.href = url;
     }
+
+    document.getElementById("a-agenda").href = agenda_url;
 }
@@ -0,0 +1,135 @@
+use crate::{
+    calendar::{to_calendar_request, CalendarQuery},
+    error::HandlerError,
+    generation,
+};
+use rocket::{
+    get,
+    http::{ContentType, RawStr},
+    request::FormParseError,
+    response::Content,
+};
+
+const DEFAULT_PER_PAGE: usize = 50;
+const MAX_PER_PAGE: usize = 200;
+
+/// Liked schedules of user `id` in our own normalised JSON schema. Accepts the same filters as
+/// the calendar endpoint, and `page` (1-based) with `per_page` for paging.
+#[get("/api/v1/users/<id>/schedules?<page>&<per_page>&<compat_cal_req_form..>")]
+pub(in crate) fn schedules(
+    id: u64,
+    page: Option<Result<usize, &RawStr>>,
+    per_page: Option<Result<usize, &RawStr>>,
+    compat_cal_req_form: Result<CalendarQuery, FormParseError>,
+) -> Result<Content<String>, HandlerError> {
+    let cal_req = to_calendar_request(compat_cal_req_form, Some(id), None)?;
+    let page = match page {
+        None => 1,
+        Some(Ok(page)) if page >= 1 => page,
+        Some(Ok(_)) => return Err(FormParseError::BadValue("page".into(), "0".into()).into()),
+        Some(Err(err)) => return Err(FormParseError::BadValue("page".into(), err).into()),
+    };
+    let per_page = match per_page {
+        None => DEFAULT_PER_PAGE,
+        Some(Ok(per_page)) if (1..=MAX_PER_PAGE).contains(&per_page) => per_page,
+        Some(Ok(per_page)) => {
+            let value = per_page.to_string();
+            return Err(FormParseError::BadValue("per_page".into(), value.as_str().into()).into());
+        }
+        Some(Err(err)) => return Err(FormParseError::BadValue("per_page".into(), err).into()),
+    };
+
+    let json = generation::generate_schedules_json(&cal_req, page, per_page)?;
+    Ok(Content(ContentType::JSON, json))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::rocket;
+    use mockito::mock;
+    use rocket::http::Status;
+    use rocket::local::Client;
+    use serde_json::Value;
+    use std::fs;
+
+    #[test]
+    fn test_schedules() {
+        let body = invoke_schedules(
+            "/api/v1/users/43224/schedules?language=en",
+            "expected_schedules.json",
+        );
+        // identities clients can group and deduplicate by
+        let permanent = &body["schedules"][0];
+        assert_eq!(permanent["event_id"], 1_152_433);
+        assert_eq!(permanent["venue"]["id"], 11672);
+        assert_eq!(permanent["permanent"], true);
+        let concert = &body["schedules"][2];
+        assert_eq!(concert["event_id"], 1_797_468);
+        assert_eq!(concert["venue"]["id"], 36746);
+        assert_eq!(concert["permanent"], false);
+    }
+
+    #[test]
+    fn test_schedules_paging() {
+        invoke_schedules(
+            "/api/v1/users/43224/schedules?language=en&page=2&per_page=2",
+            "expected_schedules_page2.json",
+        );
+    }
+
+    #[test]
+    fn test_schedules_huge_page() {
+        let goout_api_mock = mock(
+            "GET",
+            "/services/feeder/v1/events.json?tag=liked&user=43224&page=1&language=en&source=goout.strohel.eu",
+        )
+        .with_body_from_file("test_data/events.json")
+        .create();
+
+        let client = Client::new(rocket()).unwrap();
+        let mut response = client
+            .get("/api/v1/users/43224/schedules?language=en&page=18446744073709551615&per_page=200")
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let body: Value = serde_json::from_str(&response.body_string().unwrap()).unwrap();
+        assert_eq!(body["page"], 18_446_744_073_709_551_615u64);
+        assert_eq!(body["total"], 3);
+        assert_eq!(body["has_next"], false);
+        assert_eq!(body["schedules"], Value::Array(Vec::new()));
+
+        goout_api_mock.assert();
+    }
+
+    #[test]
+    fn test_invalid_schedules_bad_per_page() {
+        let client = Client::new(rocket()).unwrap();
+        let mut response =
+            client.get("/api/v1/users/43224/schedules?language=en&per_page=1000").dispatch();
+        assert_eq!(response.status(), Status::BadRequest);
+        assert_eq!(
+            response.body_string().unwrap(),
+            "Bad request: BadValue(RawStr(\"per_page\"), RawStr(\"1000\")) (see https://api.rocket.rs/v0.4/rocket/request/enum.FormParseError.html)\n"
+        );
+    }
+
+    fn invoke_schedules(path: &str, expected_file: &str) -> Value {
+        let goout_api_mock = mock(
+            "GET",
+            "/services/feeder/v1/events.json?tag=liked&user=43224&page=1&language=en&source=goout.strohel.eu",
+        )
+        .with_body_from_file("test_data/events.json")
+        .create();
+
+        let client = Client::new(rocket()).unwrap();
+        let mut response = client.get(path).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.content_type().unwrap().to_string(), "application/json");
+        let body: Value = serde_json::from_str(&response.body_string().unwrap()).unwrap();
+        let expected_json = fs::read_to_string(format!("test_data/{}", expected_file)).unwrap();
+        let expected: Value = serde_json::from_str(&expected_json).unwrap();
+        assert_eq!(body, expected);
+
+        goout_api_mock.assert();
+        body
+    }
+}
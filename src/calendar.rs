@@ -1,11 +1,26 @@
-use crate::{error::HandlerError, generation};
+use crate::{error::HandlerError, generation, l10n::Language};
+use chrono::{Datelike, Duration, NaiveDate, NaiveTime, Weekday};
+use chrono_tz::Tz;
 use rocket::{
     get,
-    http::{ContentType, RawStr, Status},
-    request::{FormParseError, LenientForm},
+    http::{Accept, ContentType, MediaType, RawStr, Status},
+    request::{FormParseError, FromFormValue, FromQuery, LenientForm, Query},
     response::Content,
-    FromForm, FromFormValue,
+    FromForm,
 };
+use std::collections::HashMap;
+
+/// Days before the end of a long-term event to remind of it in the last chance mode.
+const DEFAULT_LASTCHANCE_DAYS: u32 = 7;
+
+/// Default start hour and duration of hour-ignored events rendered as timed ones, fits most concerts
+/// and theatre plays.
+const DEFAULT_ALLDAY_HOUR: u32 = 19;
+const DEFAULT_ALLDAY_DURATION_HOURS: i64 = 3;
+
+/// Timezone used for day boundaries when the request doesn't specify one. GoOut is mainly a Czech
+/// service and its API returns times in Prague offsets.
+const DEFAULT_TIMEZONE: Tz = Tz::Europe__Prague;
 
 #[derive(Debug, FromFormValue)]
 pub(in crate) enum LongtermHandling {
@@ -15,31 +30,523 @@ pub(in crate) enum LongtermHandling {
     Split,
     /// Aggregate long-term events to max one per day.
     Aggregate,
+    /// Replace long-term events by a "last chance" reminder some days before they end.
+    LastChance,
+    /// Roll long-term events up to one entry per week, on Mondays.
+    Weekly,
+}
+
+/// Handling of permanent schedules (e.g. museum expositions), in all long-term modes.
+#[derive(Clone, Copy, Debug, FromFormValue)]
+pub(in crate) enum PermanentHandling {
+    /// Handle them like other long-term schedules.
+    Preserve,
+    /// Leave them out of the calendar.
+    Skip,
+    /// Show them as a single all-day event on their first day (or on the `after` date).
+    Once,
+}
+
+/// Handling of repeated schedules of the same event, e.g. reruns of a theatre play.
+#[derive(Clone, Copy, Debug, FromFormValue)]
+pub(in crate) enum RepeatHandling {
+    /// Keep one event per schedule.
+    Separate,
+    /// Collapse schedules of the same event into one recurring event (RRULE or RDATE).
+    Collapse,
+}
+
+/// Handling of more schedules of the same event on one day, e.g. a matinee and an evening show.
+#[derive(Clone, Copy, Debug, FromFormValue)]
+pub(in crate) enum SamedayHandling {
+    /// Keep one event per schedule.
+    Separate,
+    /// Merge them into one event spanning from the first start to the last end.
+    Merge,
+}
+
+/// Handling of timed schedules spanning more days, e.g. a festival.
+#[derive(Clone, Copy, Debug, FromFormValue)]
+pub(in crate) enum MultidayHandling {
+    /// Keep one event from the start to the end.
+    Preserve,
+    /// Split them into one event per day, keeping the daily hours.
+    Split,
+}
+
+/// Handling of hour-ignored schedules (those without time of day) that are not long-term.
+#[derive(Clone, Copy, Debug, FromFormValue)]
+pub(in crate) enum AlldayHandling {
+    /// Keep them as all-day events.
+    Preserve,
+    /// Keep them as all-day events that don't block time (TRANSP:TRANSPARENT).
+    Transparent,
+    /// Render them as timed events at a default time and duration.
+    Timed,
+}
+
+/// Representation of the served calendar.
+#[derive(Clone, Copy, Debug, FromFormValue)]
+pub(in crate) enum OutputFormat {
+    /// iCalendar (RFC 5545).
+    Ical,
+    /// jCal, JSON representation of iCalendar (RFC 7265).
+    Jcal,
+    /// xCal, XML representation of iCalendar (RFC 6321).
+    Xcal,
+    /// CSV (RFC 4180) with one row per schedule.
+    Csv,
+    /// GeoJSON (RFC 7946) with one point per venue.
+    Geojson,
+    /// iCalendar with a single VFREEBUSY component, which hides details of the events.
+    Freebusy,
+}
+
+impl OutputFormat {
+    fn content_type(self) -> ContentType {
+        match self {
+            Self::Ical => ContentType::Calendar,
+            Self::Jcal => ContentType::new("application", "calendar+json"),
+            Self::Xcal => ContentType::new("application", "calendar+xml"),
+            Self::Csv => ContentType::CSV,
+            Self::Geojson => ContentType::new("application", "geo+json"),
+            Self::Freebusy => ContentType::Calendar,
+        }
+    }
+
+    /// Find the first media type in `accept` that corresponds to some of the formats. Free/busy
+    /// shares its media type with iCalendar, so it can be only requested explicitly.
+    fn from_accept(accept: &Accept) -> Option<Self> {
+        let formats = [Self::Ical, Self::Jcal, Self::Xcal, Self::Csv, Self::Geojson];
+        accept.media_types().find_map(|media_type: &MediaType| {
+            formats.iter().copied().find(|format| format.content_type().media_type() == media_type)
+        })
+    }
+}
+
+/// Format of the feed of events.
+#[derive(Clone, Copy, Debug)]
+pub(in crate) enum FeedFormat {
+    /// RSS 2.0.
+    Rss,
+    /// Atom (RFC 4287).
+    Atom,
+}
+
+impl FeedFormat {
+    fn content_type(self) -> ContentType {
+        match self {
+            Self::Rss => ContentType::new("application", "rss+xml"),
+            Self::Atom => ContentType::new("application", "atom+xml"),
+        }
+    }
+}
+
+/// Scheme of UIDs of synthetic events (those produced by the split and aggregate long-term modes).
+/// Events that directly represent GoOut schedules have the same UIDs in both.
+#[derive(Clone, Copy, Debug, FromFormValue)]
+pub(in crate) enum UidScheme {
+    /// UIDs produced before v2 was introduced: split events have schedule ids offset by 10^12 and
+    /// 2*10^12, aggregate events are keyed only by date and thus clash between users.
+    Legacy,
+    /// Namespaced `{mode}/{identity}/{phase}@goout.net` UIDs, e.g. `split/Schedule#5/begin` or
+    /// `aggregate/User#43224/{feed}/2020-01-31`.
+    V2,
+}
+
+/// Offset of an alarm from the event it belongs to, negative values for alarms before the event.
+#[derive(Clone, Copy, Debug)]
+pub(in crate) struct AlarmTrigger(pub Duration);
+
+impl<'v> FromFormValue<'v> for AlarmTrigger {
+    type Error = &'v RawStr;
+
+    fn from_form_value(form_value: &'v RawStr) -> Result<Self, Self::Error> {
+        // url_decode() would convert leading '+' to space, use percent_decode() instead
+        let value = form_value.percent_decode().map_err(|_| form_value)?;
+        parse_ical_duration(&value).map(Self).ok_or(form_value)
+    }
+}
+
+/// Parse duration in RFC 5545 format (section 3.3.6), e.g. "-PT2H", "P1D", "-P1DT12H" or "P2W".
+fn parse_ical_duration(value: &str) -> Option<Duration> {
+    let (negative, unsigned) = match value.chars().next()? {
+        '-' => (true, &value[1..]),
+        '+' => (false, &value[1..]),
+        _ => (false, value),
+    };
+    let body = unsigned.strip_prefix('P')?;
+    let seconds = match body.find('T') {
+        None => parse_ical_duration_part(body, &[('W', 604_800)])
+            .or_else(|| parse_ical_duration_part(body, &[('D', 86_400)]))?,
+        Some(t_index) => {
+            let (date_part, time_part) = (&body[..t_index], &body[t_index + 1..]);
+            let date_seconds = match date_part {
+                "" => 0,
+                _ => parse_ical_duration_part(date_part, &[('D', 86_400)])?,
+            };
+            let time_designators = [('H', 3_600), ('M', 60), ('S', 1)];
+            date_seconds.checked_add(parse_ical_duration_part(time_part, &time_designators)?)?
+        }
+    };
+    // chrono's Duration has millisecond precision, larger values would overflow it
+    if seconds > i64::MAX / 1_000 {
+        return None;
+    }
+    let duration = Duration::seconds(seconds);
+    Some(if negative { -duration } else { duration })
+}
+
+/// Parse non-empty sequence of numbers, each followed by one of `designators` (which have to come
+/// in the given order), into number of seconds.
+fn parse_ical_duration_part(part: &str, designators: &[(char, i64)]) -> Option<i64> {
+    let mut remaining_designators = designators.iter();
+    let (mut seconds, mut number, mut empty) = (0i64, None, true);
+    for c in part.chars() {
+        if let Some(digit) = c.to_digit(10) {
+            number = Some(number.unwrap_or(0i64).checked_mul(10)?.checked_add(digit.into())?);
+            continue;
+        }
+        let (_, multiplier) = remaining_designators.find(|(designator, _)| *designator == c)?;
+        seconds = seconds.checked_add(number.take()?.checked_mul(*multiplier)?)?;
+        empty = false;
+    }
+    if number.is_some() || empty {
+        return None;
+    }
+    Some(seconds)
+}
+
+/// Colours (CSS3 colour names) of categories, keyed by lowercase category enum, e.g. "concerts".
+#[derive(Debug, Default)]
+pub(in crate) struct CategoryColors(pub HashMap<String, String>);
+
+impl<'v> FromFormValue<'v> for CategoryColors {
+    type Error = &'v RawStr;
+
+    /// Parse comma-separated `category:colour` pairs, e.g. "concerts:crimson,exhibitions:teal".
+    fn from_form_value(form_value: &'v RawStr) -> Result<Self, Self::Error> {
+        let value = form_value.url_decode().map_err(|_| form_value)?;
+        let mut colors = HashMap::new();
+        for pair in value.split(',').filter(|pair| !pair.is_empty()) {
+            let mut parts = pair.splitn(2, ':');
+            let (category, color) = match (parts.next(), parts.next()) {
+                (Some(category), Some(color)) => (category.trim(), color.trim()),
+                _ => return Err(form_value),
+            };
+            let is_word = |s: &str| !s.is_empty() && s.chars().all(|c| c.is_ascii_alphabetic());
+            if !is_word(&category.replace('_', "")) || !is_word(color) {
+                return Err(form_value);
+            }
+            colors.insert(category.to_lowercase(), color.to_lowercase());
+        }
+        Ok(Self(colors))
+    }
+}
+
+/// Geographic position in degrees.
+#[derive(Clone, Copy, Debug)]
+pub(in crate) struct Coordinates {
+    pub latitude: f64,
+    pub longitude: f64,
+}
+
+impl Coordinates {
+    /// Parse comma-separated latitude and longitude, e.g. "50.08,14.42".
+    fn parse(value: &str) -> Option<Self> {
+        let mut parts = value.splitn(2, ',');
+        let latitude = parts.next()?.trim().parse::<f64>().ok()?;
+        let longitude = parts.next()?.trim().parse::<f64>().ok()?;
+        if !(-90.0..=90.0).contains(&latitude) || !(-180.0..=180.0).contains(&longitude) {
+            return None;
+        }
+        Some(Self { latitude, longitude })
+    }
+}
+
+/// Named location to show distances of venues from, e.g. home of one of the feed users.
+#[derive(Debug)]
+pub(in crate) struct HomeLocation {
+    pub name: String,
+    pub coordinates: Coordinates,
+}
+
+/// Filters of schedules, all of them repeatable. Values of one filter are alternatives, a schedule
+/// has to pass all given filters. Names are compared case-insensitively.
+#[derive(Debug, Default)]
+pub(in crate) struct ScheduleFilters {
+    /// Lowercase category kinds, e.g. "concerts", at least one of which a schedule has to have.
+    pub categories: Vec<String>,
+    /// Lowercase category kinds a schedule must not have.
+    pub exclude_categories: Vec<String>,
+    pub venues: Vec<String>,
+    /// City names, which also match their districts, e.g. "praha" matches "Praha 5".
+    pub cities: Vec<String>,
+    /// Country names, as GoOut gives them in the language of the event.
+    pub countries: Vec<String>,
+    /// Maximum lowest price in the currency of the schedule, whatever it is (prices are not
+    /// converted). Schedules without price pass.
+    pub max_price: Option<f64>,
+    /// Centre and radius in kilometres of the area venues have to be in.
+    pub near: Option<(Coordinates, f64)>,
+    /// Days of week of timed schedules (in their local timezone) and of days emitted for long-term
+    /// ones (in `tz`). All days if empty.
+    pub days: Vec<Weekday>,
+    /// Start of the time window (in local timezone) of timed schedules.
+    pub from_time: Option<NaiveTime>,
+    /// End of the time window (in local timezone) of timed schedules, on the next day if not after
+    /// `from_time`.
+    pub to_time: Option<NaiveTime>,
+}
+
+impl ScheduleFilters {
+    pub fn allows_day(&self, date: NaiveDate) -> bool {
+        self.days.is_empty() || self.days.contains(&date.weekday())
+    }
+}
+
+/// Parse comma-separated days of week, e.g. "mon,tue" or "weekend".
+fn parse_days(value: &str) -> Option<Vec<Weekday>> {
+    use Weekday::*;
+    let mut days = Vec::new();
+    for day in value.split(',').map(str::trim) {
+        match day.to_lowercase().as_str() {
+            "weekdays" => days.extend_from_slice(&[Mon, Tue, Wed, Thu, Fri]),
+            "weekend" => days.extend_from_slice(&[Sat, Sun]),
+            day => days.push(day.parse().ok()?),
+        }
+    }
+    Some(days)
 }
 
 pub(in crate) struct CalendarRequest {
     pub id: u64,
-    pub language: String,
+    pub language: Language,
     pub after: Option<String>,
+    /// Only schedules starting before this date. Filtered by us, GoOut API doesn't support it.
+    pub before: Option<NaiveDate>,
     pub longterm: LongtermHandling,
+    /// If set, schedules spanning at least this many days are long-term and others are not,
+    /// regardless of what GoOut says. At least 1.
+    pub longterm_min_days: Option<u32>,
+    /// Days before the end of long-term events for the last chance mode, at least 1.
+    pub lastchance_days: u32,
+    pub permanent: PermanentHandling,
+    pub tz: Tz,
+    /// Alarm for events with time of day.
+    pub alarm: Option<AlarmTrigger>,
+    /// Alarm for all-day events, including long-term ones.
+    pub alarm_allday: Option<AlarmTrigger>,
+    pub colors: CategoryColors,
+    pub uids: UidScheme,
+    pub format: OutputFormat,
+    /// Whether to prepend UTF-8 byte order mark to CSV output, for Excel.
+    pub csv_bom: bool,
+    /// Whether all-day (hour-ignored) schedules make the user busy in free/busy output.
+    pub busy_allday: bool,
+    /// Whether long-term schedules make the user busy in free/busy output.
+    pub busy_longterm: bool,
+    pub repeats: RepeatHandling,
+    pub samedays: SamedayHandling,
+    pub multiday: MultidayHandling,
+    pub allday: AlldayHandling,
+    /// Start time in `tz` of hour-ignored events in the timed all-day mode.
+    pub allday_time: NaiveTime,
+    /// Duration of hour-ignored events in the timed all-day mode, positive and shorter than a day.
+    pub allday_duration: Duration,
+    pub filters: ScheduleFilters,
+    /// Named locations to add distances of venues from to event descriptions.
+    pub homes: Vec<HomeLocation>,
 }
 
 // Compatibility struct to accept both v1 (split: bool) and v2 (longterm: LongtermHandling) of the API
 #[derive(Debug, FromForm)]
 pub(in crate) struct CompatibleCalendarRequest<'a> {
-    id: u64,
-    language: String,
+    // optional because some endpoints take the user ID from path
+    id: Option<Result<u64, &'a RawStr>>,
+    language: Language,
     after: Option<String>,
+    before: Option<&'a RawStr>,
     // Option needs to be there to tell false from not present; Result needs to be there to tell parse error from not present:
     split: Option<Result<bool, &'a RawStr>>,
     longterm: Option<Result<LongtermHandling, &'a RawStr>>,
+    lastchance_days: Option<Result<u32, &'a RawStr>>,
+    longterm_min_days: Option<Result<u32, &'a RawStr>>,
+    permanent: Option<Result<PermanentHandling, &'a RawStr>>,
+    tz: Option<&'a RawStr>,
+    alarm: Option<Result<AlarmTrigger, &'a RawStr>>,
+    alarm_allday: Option<Result<AlarmTrigger, &'a RawStr>>,
+    colors: Option<Result<CategoryColors, &'a RawStr>>,
+    uids: Option<Result<UidScheme, &'a RawStr>>,
+    format: Option<Result<OutputFormat, &'a RawStr>>,
+    bom: Option<Result<bool, &'a RawStr>>,
+    busy_allday: Option<Result<bool, &'a RawStr>>,
+    busy_longterm: Option<Result<bool, &'a RawStr>>,
+    repeats: Option<Result<RepeatHandling, &'a RawStr>>,
+    samedays: Option<Result<SamedayHandling, &'a RawStr>>,
+    multiday: Option<Result<MultidayHandling, &'a RawStr>>,
+    allday: Option<Result<AlldayHandling, &'a RawStr>>,
+    allday_time: Option<&'a RawStr>,
+    allday_duration: Option<&'a RawStr>,
+}
+
+/// Query of the calendar endpoints: the compatibility form, repeatable schedule filters and home
+/// locations, which the derived form cannot express, as it keeps only the last value of a repeated
+/// parameter.
+pub(in crate) struct CalendarQuery<'q> {
+    compat_cal_req: CompatibleCalendarRequest<'q>,
+    filters: ScheduleFilters,
+    homes: Vec<HomeLocation>,
+}
+
+impl<'q> FromQuery<'q> for CalendarQuery<'q> {
+    type Error = FormParseError<'q>;
+
+    fn from_query(query: Query<'q>) -> Result<Self, Self::Error> {
+        let compat_cal_req = LenientForm::<CompatibleCalendarRequest>::from_query(query.clone())?;
+        let compat_cal_req = compat_cal_req.into_inner();
+        let mut filters = ScheduleFilters::default();
+        let mut homes = Vec::new();
+        let (mut near, mut radius_km) = (None, None);
+        for item in query {
+            let bad_value = || FormParseError::BadValue(item.key, item.value);
+            let name = || {
+                let value = item.value.url_decode().map_err(|_| bad_value())?;
+                match value.trim() {
+                    "" => Err(bad_value()),
+                    trimmed => Ok(trimmed.to_lowercase()),
+                }
+            };
+            match item.key.as_str() {
+                "category" => filters.categories.push(name()?),
+                "exclude_category" => filters.exclude_categories.push(name()?),
+                "venue" => filters.venues.push(name()?),
+                "city" => filters.cities.push(name()?),
+                "country" => filters.countries.push(name()?),
+                "max_price" => {
+                    let max_price = item.value.as_str().parse::<f64>().map_err(|_| bad_value())?;
+                    if max_price.is_nan() || max_price < 0.0 {
+                        return Err(bad_value());
+                    }
+                    filters.max_price = Some(max_price);
+                }
+                "near" => {
+                    let value = item.value.url_decode().map_err(|_| bad_value())?;
+                    near = Some(Coordinates::parse(&value).ok_or_else(bad_value)?);
+                }
+                "radius_km" => {
+                    let radius = item.value.as_str().parse::<f64>().map_err(|_| bad_value())?;
+                    if radius.is_nan() || radius <= 0.0 {
+                        return Err(bad_value());
+                    }
+                    radius_km = Some(radius);
+                }
+                "days" => {
+                    let value = item.value.url_decode().map_err(|_| bad_value())?;
+                    filters.days.extend(parse_days(&value).ok_or_else(bad_value)?);
+                }
+                "from_time" | "to_time" => {
+                    let value = item.value.url_decode().map_err(|_| bad_value())?;
+                    let time =
+                        NaiveTime::parse_from_str(&value, "%H:%M").map_err(|_| bad_value())?;
+                    if item.key == "from_time" {
+                        filters.from_time = Some(time);
+                    } else {
+                        filters.to_time = Some(time);
+                    }
+                }
+                "home" => {
+                    // "name:latitude,longitude", the name may contain colons
+                    let value = item.value.url_decode().map_err(|_| bad_value())?;
+                    let mut parts = value.rsplitn(2, ':');
+                    let coordinates = parts.next().and_then(Coordinates::parse);
+                    let name = parts.next().map(str::trim).filter(|name| !name.is_empty());
+                    match (name, coordinates) {
+                        (Some(name), Some(coordinates)) => {
+                            homes.push(HomeLocation { name: name.to_string(), coordinates })
+                        }
+                        _ => return Err(bad_value()),
+                    }
+                }
+                _ => {}
+            }
+        }
+        filters.near = match (near, radius_km) {
+            (None, None) => None,
+            (Some(near), Some(radius_km)) => Some((near, radius_km)),
+            (Some(_), None) => return Err(FormParseError::Missing("radius_km".into())),
+            (None, Some(_)) => return Err(FormParseError::Missing("near".into())),
+        };
+        Ok(Self { compat_cal_req, filters, homes })
+    }
 }
 
 #[get("/services/feeder/usercalendar.ics?<compat_cal_req_form..>")]
 pub(in crate) fn serve(
-    compat_cal_req_form: Result<LenientForm<CompatibleCalendarRequest>, FormParseError>,
+    compat_cal_req_form: Result<CalendarQuery, FormParseError>,
+    accept: Option<&Accept>,
 ) -> Result<Content<String>, HandlerError> {
-    let compat_cal_req = compat_cal_req_form?.into_inner();
+    let cal_req = to_calendar_request(compat_cal_req_form, None, accept)?;
+
+    // Normally, we would stream to output as soon as we get first page, but
+    // instead we load all pages first and only then start replying. We can
+    // afford this, because the calendar endpoint would be typically called
+    // infrequently and in non-interactive manner. Advantage is that we can
+    // properly report errors on HTTP level, and simplicity. Disadvantage is
+    // high latency of first byte served.
+    let calendar_string = generation::generate(&cal_req)?;
+    Ok(Content(cal_req.format.content_type(), calendar_string))
+}
+
+#[get("/services/feeder/usercalendar.rss?<compat_cal_req_form..>")]
+pub(in crate) fn serve_rss(
+    compat_cal_req_form: Result<CalendarQuery, FormParseError>,
+) -> Result<Content<String>, HandlerError> {
+    serve_feed(compat_cal_req_form, FeedFormat::Rss)
+}
+
+#[get("/services/feeder/usercalendar.atom?<compat_cal_req_form..>")]
+pub(in crate) fn serve_atom(
+    compat_cal_req_form: Result<CalendarQuery, FormParseError>,
+) -> Result<Content<String>, HandlerError> {
+    serve_feed(compat_cal_req_form, FeedFormat::Atom)
+}
+
+/// Server-rendered agenda of the calendar, grouped by day. Accepts the same parameters as the
+/// calendar endpoint.
+#[get("/agenda/<id>?<compat_cal_req_form..>")]
+pub(in crate) fn agenda(
+    id: u64,
+    compat_cal_req_form: Result<CalendarQuery, FormParseError>,
+) -> Result<Content<String>, HandlerError> {
+    let cal_req = to_calendar_request(compat_cal_req_form, Some(id), None)?;
+    let agenda_string = generation::generate_agenda(&cal_req)?;
+    Ok(Content(ContentType::HTML, agenda_string))
+}
+
+fn serve_feed(
+    compat_cal_req_form: Result<CalendarQuery, FormParseError>,
+    feed_format: FeedFormat,
+) -> Result<Content<String>, HandlerError> {
+    let cal_req = to_calendar_request(compat_cal_req_form, None, None)?;
+    let feed_string = generation::generate_feed(&cal_req, feed_format)?;
+    Ok(Content(feed_format.content_type(), feed_string))
+}
+
+/// Validate calendar request parameters, user ID is taken from `path_id` if given, otherwise from
+/// the `id` parameter.
+pub(in crate) fn to_calendar_request(
+    compat_cal_req_form: Result<CalendarQuery, FormParseError>,
+    path_id: Option<u64>,
+    accept: Option<&Accept>,
+) -> Result<CalendarRequest, HandlerError> {
+    let CalendarQuery { compat_cal_req, filters, homes } = compat_cal_req_form?;
+    let id = match (path_id, compat_cal_req.id) {
+        (Some(id), _) => id,
+        (None, Some(Ok(id))) => id,
+        (None, Some(Err(err))) => return Err(FormParseError::BadValue("id".into(), err).into()),
+        (None, None) => return Err(FormParseError::Missing("id".into()).into()),
+    };
     // For Err variants, we mimic internal Rocket behaviour: return the same parse error
     let longterm = match (compat_cal_req.split, compat_cal_req.longterm) {
         (None, None) => LongtermHandling::Preserve, // default
@@ -56,21 +563,153 @@ pub(in crate) fn serve(
                 .to_string(),
         )),
     };
-    let cal_req = CalendarRequest {
-        id: compat_cal_req.id,
+    let lastchance_days = match compat_cal_req.lastchance_days {
+        None => DEFAULT_LASTCHANCE_DAYS,
+        Some(Ok(days)) if days >= 1 => days,
+        Some(Ok(_)) => {
+            return Err(FormParseError::BadValue("lastchance_days".into(), "0".into()).into())
+        }
+        Some(Err(err)) => {
+            return Err(FormParseError::BadValue("lastchance_days".into(), err).into())
+        }
+    };
+    let longterm_min_days = match compat_cal_req.longterm_min_days {
+        None => None,
+        Some(Ok(days)) if days >= 1 => Some(days),
+        Some(Ok(_)) => {
+            return Err(FormParseError::BadValue("longterm_min_days".into(), "0".into()).into())
+        }
+        Some(Err(err)) => {
+            return Err(FormParseError::BadValue("longterm_min_days".into(), err).into())
+        }
+    };
+    let permanent = compat_cal_req
+        .permanent
+        .transpose()
+        .map_err(|err| FormParseError::BadValue("permanent".into(), err))?
+        .unwrap_or(PermanentHandling::Preserve);
+    let before = match compat_cal_req.before {
+        None => None,
+        Some(raw_before) => Some(
+            NaiveDate::parse_from_str(raw_before.as_str(), "%Y-%m-%d")
+                .map_err(|_| FormParseError::BadValue("before".into(), raw_before))?,
+        ),
+    };
+    let tz = match compat_cal_req.tz {
+        None => DEFAULT_TIMEZONE,
+        Some(raw_tz) => raw_tz
+            .url_decode()
+            .ok()
+            .and_then(|tz_name| tz_name.parse().ok())
+            .ok_or_else(|| FormParseError::BadValue("tz".into(), raw_tz))?,
+    };
+    let alarm = compat_cal_req
+        .alarm
+        .transpose()
+        .map_err(|err| FormParseError::BadValue("alarm".into(), err))?;
+    let alarm_allday = compat_cal_req
+        .alarm_allday
+        .transpose()
+        .map_err(|err| FormParseError::BadValue("alarm_allday".into(), err))?;
+    let colors = compat_cal_req
+        .colors
+        .transpose()
+        .map_err(|err| FormParseError::BadValue("colors".into(), err))?
+        .unwrap_or_default();
+    // Existing subscriptions (without the parameter) keep legacy UIDs so that clients don't
+    // duplicate their events, the index page generates URLs with v2.
+    let uids = compat_cal_req
+        .uids
+        .transpose()
+        .map_err(|err| FormParseError::BadValue("uids".into(), err))?
+        .unwrap_or(UidScheme::Legacy);
+    // Explicit format parameter takes precedence over content negotiation.
+    let format = compat_cal_req
+        .format
+        .transpose()
+        .map_err(|err| FormParseError::BadValue("format".into(), err))?
+        .or_else(|| accept.and_then(OutputFormat::from_accept))
+        .unwrap_or(OutputFormat::Ical);
+    let csv_bom = compat_cal_req
+        .bom
+        .transpose()
+        .map_err(|err| FormParseError::BadValue("bom".into(), err))?
+        .unwrap_or(false);
+    let busy_allday = compat_cal_req
+        .busy_allday
+        .transpose()
+        .map_err(|err| FormParseError::BadValue("busy_allday".into(), err))?
+        .unwrap_or(false);
+    let busy_longterm = compat_cal_req
+        .busy_longterm
+        .transpose()
+        .map_err(|err| FormParseError::BadValue("busy_longterm".into(), err))?
+        .unwrap_or(false);
+    let repeats = compat_cal_req
+        .repeats
+        .transpose()
+        .map_err(|err| FormParseError::BadValue("repeats".into(), err))?
+        .unwrap_or(RepeatHandling::Separate);
+    let samedays = compat_cal_req
+        .samedays
+        .transpose()
+        .map_err(|err| FormParseError::BadValue("samedays".into(), err))?
+        .unwrap_or(SamedayHandling::Separate);
+    let multiday = compat_cal_req
+        .multiday
+        .transpose()
+        .map_err(|err| FormParseError::BadValue("multiday".into(), err))?
+        .unwrap_or(MultidayHandling::Preserve);
+    let allday = compat_cal_req
+        .allday
+        .transpose()
+        .map_err(|err| FormParseError::BadValue("allday".into(), err))?
+        .unwrap_or(AlldayHandling::Preserve);
+    let allday_time = match compat_cal_req.allday_time {
+        None => NaiveTime::from_hms(DEFAULT_ALLDAY_HOUR, 0, 0),
+        Some(raw_time) => raw_time
+            .url_decode()
+            .ok()
+            .and_then(|time| NaiveTime::parse_from_str(&time, "%H:%M").ok())
+            .ok_or_else(|| FormParseError::BadValue("allday_time".into(), raw_time))?,
+    };
+    // reuse duration parsing of alarm triggers, but only positive durations make sense here, and
+    // the event repeats every day
+    let allday_duration = match compat_cal_req.allday_duration {
+        None => Duration::hours(DEFAULT_ALLDAY_DURATION_HOURS),
+        Some(raw_duration) => AlarmTrigger::from_form_value(raw_duration)
+            .ok()
+            .map(|AlarmTrigger(duration)| duration)
+            .filter(|duration| *duration > Duration::zero() && *duration < Duration::days(1))
+            .ok_or_else(|| FormParseError::BadValue("allday_duration".into(), raw_duration))?,
+    };
+    Ok(CalendarRequest {
+        id,
         language: compat_cal_req.language,
         after: compat_cal_req.after,
+        before,
         longterm,
-    };
-
-    // Normally, we would stream to output as soon as we get first page, but
-    // instead we load all pages first and only then start replying. We can
-    // afford this, because the calendar endpoint would be typically called
-    // infrequently and in non-interactive manner. Advantage is that we can
-    // properly report errors on HTTP level, and simplicity. Disadvantage is
-    // high latency of first byte served.
-    let calendar_string = generation::generate(&cal_req)?;
-    Ok(Content(ContentType::Calendar, calendar_string))
+        longterm_min_days,
+        lastchance_days,
+        permanent,
+        tz,
+        alarm,
+        alarm_allday,
+        colors,
+        uids,
+        format,
+        csv_bom,
+        busy_allday,
+        busy_longterm,
+        repeats,
+        samedays,
+        multiday,
+        allday,
+        allday_time,
+        allday_duration,
+        filters,
+        homes,
+    })
 }
 
 #[cfg(test)]
@@ -79,7 +718,8 @@ mod tests {
     use crate::rocket;
     use mockito::mock;
     use pretty_assertions::assert_eq;
-    use rocket::local::Client;
+    use rocket::http::Header;
+    use rocket::local::{Client, LocalResponse};
     use std::fs;
 
     #[test]
@@ -130,6 +770,360 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_serve_tz() {
+        invoke_serve(
+            "/services/feeder/usercalendar.ics?id=43224&language=en&tz=Europe%2FPrague",
+            "test_data/expected_nonsplit.ical",
+        );
+    }
+
+    #[test]
+    fn test_serve_longterm_aggregate_tz() {
+        invoke_serve(
+            "/services/feeder/usercalendar.ics?id=43224&language=en&longterm=aggregate&tz=America/New_York",
+            "test_data/expected_aggregate_new_york.ical",
+        );
+    }
+
+    #[test]
+    fn test_serve_alarm() {
+        invoke_serve(
+            "/services/feeder/usercalendar.ics?id=43224&language=en&alarm=-PT2H&alarm_allday=-P1D",
+            "test_data/expected_nonsplit_alarm.ical",
+        );
+    }
+
+    #[test]
+    fn test_serve_longterm_split_alarm() {
+        invoke_serve(
+            "/services/feeder/usercalendar.ics?id=43224&language=en&longterm=split&alarm_allday=-PT12H",
+            "test_data/expected_split_alarm.ical",
+        );
+    }
+
+    #[test]
+    fn test_serve_longterm_aggregate_alarm() {
+        invoke_serve(
+            "/services/feeder/usercalendar.ics?id=43224&language=en&longterm=aggregate&alarm=-PT30M&alarm_allday=-P1D",
+            "test_data/expected_aggregate_alarm.ical",
+        );
+    }
+
+    #[test]
+    fn test_serve_colors() {
+        invoke_serve(
+            "/services/feeder/usercalendar.ics?id=43224&language=en&colors=concerts:Crimson,exhibitions:teal",
+            "test_data/expected_nonsplit_colors.ical",
+        );
+    }
+
+    #[test]
+    fn test_serve_longterm_split_uids_v2() {
+        invoke_serve(
+            "/services/feeder/usercalendar.ics?id=43224&language=en&longterm=split&uids=v2",
+            "test_data/expected_split_uids_v2.ical",
+        );
+    }
+
+    #[test]
+    fn test_serve_longterm_aggregate_uids_v2() {
+        invoke_serve(
+            "/services/feeder/usercalendar.ics?id=43224&language=en&longterm=aggregate&uids=v2",
+            "test_data/expected_aggregate_uids_v2.ical",
+        );
+    }
+
+    #[test]
+    fn test_serve_jcal() {
+        invoke_serve_format(
+            "/services/feeder/usercalendar.ics?id=43224&language=en&longterm=aggregate&format=jcal",
+            None,
+            "application/calendar+json",
+            "test_data/expected_aggregate.jcal",
+        );
+    }
+
+    #[test]
+    fn test_serve_jcal_accept() {
+        invoke_serve_format(
+            "/services/feeder/usercalendar.ics?id=43224&language=en&longterm=aggregate",
+            Some("text/html;q=0.9, application/calendar+json"),
+            "application/calendar+json",
+            "test_data/expected_aggregate.jcal",
+        );
+    }
+
+    #[test]
+    fn test_serve_xcal() {
+        invoke_serve_format(
+            "/services/feeder/usercalendar.ics?id=43224&language=en&longterm=split&format=xcal",
+            None,
+            "application/calendar+xml",
+            "test_data/expected_split.xcal",
+        );
+    }
+
+    #[test]
+    fn test_serve_xcal_accept() {
+        invoke_serve_format(
+            "/services/feeder/usercalendar.ics?id=43224&language=en&longterm=split",
+            Some("application/calendar+xml"),
+            "application/calendar+xml",
+            "test_data/expected_split.xcal",
+        );
+    }
+
+    #[test]
+    fn test_serve_csv() {
+        invoke_serve_format(
+            "/services/feeder/usercalendar.ics?id=43224&language=en&format=csv",
+            None,
+            "text/csv; charset=utf-8",
+            "test_data/expected.csv",
+        );
+    }
+
+    #[test]
+    fn test_serve_csv_bom() {
+        invoke_serve_format(
+            "/services/feeder/usercalendar.ics?id=43224&language=en&format=csv&bom=true&tz=America%2FNew_York",
+            None,
+            "text/csv; charset=utf-8",
+            "test_data/expected_bom_new_york.csv",
+        );
+    }
+
+    #[test]
+    fn test_serve_geojson() {
+        invoke_serve_format(
+            "/services/feeder/usercalendar.ics?id=43224&language=en&format=geojson",
+            None,
+            "application/geo+json",
+            "test_data/expected.geojson",
+        );
+    }
+
+    #[test]
+    fn test_serve_weekly() {
+        invoke_serve_ex(
+            "/services/feeder/usercalendar.ics?id=43224&language=en&longterm=weekly&after=2019-12-10",
+            "tag=liked&user=43224&page=1&language=en&source=goout.strohel.eu&after=2019-12-10",
+            "events.json",
+            "test_data/expected_weekly.ical",
+        );
+    }
+
+    #[test]
+    fn test_serve_lastchance() {
+        invoke_serve(
+            "/services/feeder/usercalendar.ics?id=43224&language=en&longterm=lastchance",
+            "test_data/expected_lastchance.ical",
+        );
+    }
+
+    #[test]
+    fn test_serve_lastchance_days() {
+        invoke_serve(
+            "/services/feeder/usercalendar.ics?id=43224&language=en&longterm=lastchance&lastchance_days=3",
+            "test_data/expected_lastchance_days.ical",
+        );
+    }
+
+    #[test]
+    fn test_serve_permanent_skip() {
+        invoke_serve(
+            "/services/feeder/usercalendar.ics?id=43224&language=en&longterm=split&permanent=skip",
+            "test_data/expected_split_permanent_skip.ical",
+        );
+    }
+
+    #[test]
+    fn test_serve_permanent_once() {
+        invoke_serve_ex(
+            "/services/feeder/usercalendar.ics?id=43224&language=en&longterm=aggregate&permanent=once&after=2020-01-15",
+            "tag=liked&user=43224&page=1&language=en&source=goout.strohel.eu&after=2020-01-15",
+            "events.json",
+            "test_data/expected_aggregate_permanent_once.ical",
+        );
+    }
+
+    #[test]
+    fn test_serve_longterm_min_days() {
+        invoke_serve(
+            "/services/feeder/usercalendar.ics?id=43224&language=en&longterm=split&longterm_min_days=60",
+            "test_data/expected_split_longterm_min_days.ical",
+        );
+    }
+
+    #[test]
+    fn test_serve_allday_timed() {
+        invoke_serve(
+            "/services/feeder/usercalendar.ics?id=43224&language=en&longterm_min_days=60&allday=timed&allday_time=10:00&allday_duration=PT8H",
+            "test_data/expected_allday_timed.ical",
+        );
+    }
+
+    #[test]
+    fn test_serve_allday_transparent() {
+        invoke_serve(
+            "/services/feeder/usercalendar.ics?id=43224&language=en&longterm_min_days=60&allday=transparent",
+            "test_data/expected_allday_transparent.ical",
+        );
+    }
+
+    #[test]
+    fn test_serve_filter_categories() {
+        invoke_serve(
+            "/services/feeder/usercalendar.ics?id=43224&language=en&category=concerts&category=Theatre",
+            "test_data/expected_filter_concerts.ical",
+        );
+    }
+
+    #[test]
+    fn test_serve_filter_place() {
+        invoke_serve(
+            "/services/feeder/usercalendar.ics?id=43224&language=en&city=praha&country=Czechia&country=%C4%8Cesko&venue=Jaroslav+Fragner+Gallery",
+            "test_data/expected_filter_place.ical",
+        );
+    }
+
+    #[test]
+    fn test_serve_filter_aggregate() {
+        invoke_serve(
+            "/services/feeder/usercalendar.ics?id=43224&language=en&longterm=aggregate&exclude_category=concerts&max_price=100",
+            "test_data/expected_filter_aggregate.ical",
+        );
+    }
+
+    #[test]
+    fn test_serve_filter_near() {
+        invoke_serve(
+            "/services/feeder/usercalendar.ics?id=43224&language=en&near=52.52,13.405&radius_km=50",
+            "test_data/expected_filter_near.ical",
+        );
+    }
+
+    #[test]
+    fn test_serve_homes() {
+        invoke_serve(
+            "/services/feeder/usercalendar.ics?id=43224&language=en&home=Prague:50.0875,14.4214&home=Berlin:52.52,13.405",
+            "test_data/expected_homes.ical",
+        );
+    }
+
+    #[test]
+    fn test_serve_homes_lastchance() {
+        invoke_serve(
+            "/services/feeder/usercalendar.ics?id=43224&language=en&longterm=lastchance&home=Prague:50.0875,14.4214",
+            "test_data/expected_homes_lastchance.ical",
+        );
+    }
+
+    #[test]
+    fn test_serve_filter_weekend_split() {
+        invoke_serve(
+            "/services/feeder/usercalendar.ics?id=43224&language=en&longterm=split&days=sat,sun",
+            "test_data/expected_filter_weekend_split.ical",
+        );
+    }
+
+    #[test]
+    fn test_serve_filter_time() {
+        invoke_serve(
+            "/services/feeder/usercalendar.ics?id=43224&language=en&days=weekdays&from_time=18:00&to_time=23:00",
+            "test_data/expected_filter_time.ical",
+        );
+    }
+
+    #[test]
+    fn test_serve_repeats_collapse() {
+        // reruns of the concert, one of them cancelled, become one recurring event
+        invoke_serve_ex(
+            "/services/feeder/usercalendar.ics?id=43224&language=en&repeats=collapse",
+            "tag=liked&user=43224&page=1&language=en&source=goout.strohel.eu",
+            "events_repeats.json",
+            "test_data/expected_repeats_collapse.ical",
+        );
+    }
+
+    #[test]
+    fn test_serve_samedays_merge() {
+        // the matinee and the evening concert on one day become one event
+        invoke_serve_ex(
+            "/services/feeder/usercalendar.ics?id=43224&language=en&samedays=merge",
+            "tag=liked&user=43224&page=1&language=en&source=goout.strohel.eu",
+            "events_samedays.json",
+            "test_data/expected_samedays_merge.ical",
+        );
+    }
+
+    #[test]
+    fn test_serve_freebusy() {
+        invoke_serve(
+            "/services/feeder/usercalendar.ics?id=43224&language=en&format=freebusy",
+            "test_data/expected_freebusy.ical",
+        );
+    }
+
+    #[test]
+    fn test_serve_freebusy_allday() {
+        invoke_serve(
+            "/services/feeder/usercalendar.ics?id=43224&language=en&format=freebusy&busy_allday=true&longterm_min_days=60",
+            "test_data/expected_freebusy_allday.ical",
+        );
+    }
+
+    #[test]
+    fn test_serve_freebusy_window() {
+        invoke_serve_ex(
+            "/services/feeder/usercalendar.ics?id=43224&language=en&format=freebusy&after=2020-03-01&before=2020-03-10&busy_allday=true&busy_longterm=true",
+            "tag=liked&user=43224&page=1&language=en&source=goout.strohel.eu&after=2020-03-01",
+            "events.json",
+            "test_data/expected_freebusy_window.ical",
+        );
+    }
+
+    #[test]
+    fn test_serve_format_over_accept() {
+        invoke_serve_format(
+            "/services/feeder/usercalendar.ics?id=43224&language=en&longterm=aggregate&format=ical",
+            Some("application/calendar+json"),
+            "text/calendar",
+            "test_data/expected_aggregate.ical",
+        );
+    }
+
+    #[test]
+    fn test_serve_rss() {
+        invoke_serve_format(
+            "/services/feeder/usercalendar.rss?id=43224&language=en",
+            None,
+            "application/rss+xml",
+            "test_data/expected.rss",
+        );
+    }
+
+    #[test]
+    fn test_serve_atom() {
+        invoke_serve_format(
+            "/services/feeder/usercalendar.atom?id=43224&language=en",
+            None,
+            "application/atom+xml",
+            "test_data/expected.atom",
+        );
+    }
+
+    #[test]
+    fn test_agenda() {
+        invoke_serve_format(
+            "/agenda/43224?language=en&longterm=aggregate",
+            None,
+            "text/html; charset=utf-8",
+            "test_data/expected_agenda.html",
+        );
+    }
+
     #[test]
     fn test_serve_after() {
         invoke_serve_ex(
@@ -196,6 +1190,116 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_invalid_serve_zero_lastchance_days() {
+        invoke_serve_lowlevel(
+            "/services/feeder/usercalendar.ics?id=123&language=en&longterm=lastchance&lastchance_days=0",
+            Status::BadRequest,
+            "text/plain; charset=utf-8",
+            "Bad request: BadValue(RawStr(\"lastchance_days\"), RawStr(\"0\")) (see https://api.rocket.rs/v0.4/rocket/request/enum.FormParseError.html)\n"
+        );
+    }
+
+    #[test]
+    fn test_invalid_serve_zero_longterm_min_days() {
+        invoke_serve_lowlevel(
+            "/services/feeder/usercalendar.ics?id=123&language=en&longterm_min_days=0",
+            Status::BadRequest,
+            "text/plain; charset=utf-8",
+            "Bad request: BadValue(RawStr(\"longterm_min_days\"), RawStr(\"0\")) (see https://api.rocket.rs/v0.4/rocket/request/enum.FormParseError.html)\n"
+        );
+    }
+
+    #[test]
+    fn test_invalid_serve_allday_time() {
+        invoke_serve_lowlevel(
+            "/services/feeder/usercalendar.ics?id=123&language=en&allday=timed&allday_time=25:00",
+            Status::BadRequest,
+            "text/plain; charset=utf-8",
+            "Bad request: BadValue(RawStr(\"allday_time\"), RawStr(\"25:00\")) (see https://api.rocket.rs/v0.4/rocket/request/enum.FormParseError.html)\n"
+        );
+    }
+
+    #[test]
+    fn test_invalid_serve_negative_allday_duration() {
+        invoke_serve_lowlevel(
+            "/services/feeder/usercalendar.ics?id=123&language=en&allday=timed&allday_duration=-PT1H",
+            Status::BadRequest,
+            "text/plain; charset=utf-8",
+            "Bad request: BadValue(RawStr(\"allday_duration\"), RawStr(\"-PT1H\")) (see https://api.rocket.rs/v0.4/rocket/request/enum.FormParseError.html)\n"
+        );
+    }
+
+    #[test]
+    fn test_invalid_serve_max_price() {
+        invoke_serve_lowlevel(
+            "/services/feeder/usercalendar.ics?id=123&language=en&max_price=-5",
+            Status::BadRequest,
+            "text/plain; charset=utf-8",
+            "Bad request: BadValue(RawStr(\"max_price\"), RawStr(\"-5\")) (see https://api.rocket.rs/v0.4/rocket/request/enum.FormParseError.html)\n"
+        );
+    }
+
+    #[test]
+    fn test_invalid_serve_empty_category() {
+        invoke_serve_lowlevel(
+            "/services/feeder/usercalendar.ics?id=123&language=en&category=concerts&category=",
+            Status::BadRequest,
+            "text/plain; charset=utf-8",
+            "Bad request: BadValue(RawStr(\"category\"), RawStr(\"\")) (see https://api.rocket.rs/v0.4/rocket/request/enum.FormParseError.html)\n"
+        );
+    }
+
+    #[test]
+    fn test_invalid_serve_near_without_radius() {
+        invoke_serve_lowlevel(
+            "/services/feeder/usercalendar.ics?id=123&language=en&near=50.08,14.42",
+            Status::BadRequest,
+            "text/plain; charset=utf-8",
+            "Bad request: Missing(RawStr(\"radius_km\")) (see https://api.rocket.rs/v0.4/rocket/request/enum.FormParseError.html)\n"
+        );
+    }
+
+    #[test]
+    fn test_invalid_serve_bad_near() {
+        invoke_serve_lowlevel(
+            "/services/feeder/usercalendar.ics?id=123&language=en&near=95,14.42&radius_km=10",
+            Status::BadRequest,
+            "text/plain; charset=utf-8",
+            "Bad request: BadValue(RawStr(\"near\"), RawStr(\"95,14.42\")) (see https://api.rocket.rs/v0.4/rocket/request/enum.FormParseError.html)\n"
+        );
+    }
+
+    #[test]
+    fn test_invalid_serve_bad_days() {
+        invoke_serve_lowlevel(
+            "/services/feeder/usercalendar.ics?id=123&language=en&days=mon,someday",
+            Status::BadRequest,
+            "text/plain; charset=utf-8",
+            "Bad request: BadValue(RawStr(\"days\"), RawStr(\"mon,someday\")) (see https://api.rocket.rs/v0.4/rocket/request/enum.FormParseError.html)\n"
+        );
+    }
+
+    #[test]
+    fn test_invalid_serve_too_long_allday_duration() {
+        invoke_serve_lowlevel(
+            "/services/feeder/usercalendar.ics?id=123&language=en&allday=timed&allday_duration=P1D",
+            Status::BadRequest,
+            "text/plain; charset=utf-8",
+            "Bad request: BadValue(RawStr(\"allday_duration\"), RawStr(\"P1D\")) (see https://api.rocket.rs/v0.4/rocket/request/enum.FormParseError.html)\n"
+        );
+    }
+
+    #[test]
+    fn test_invalid_serve_bad_language() {
+        invoke_serve_lowlevel(
+            "/services/feeder/usercalendar.ics?id=123&language=klingon",
+            Status::BadRequest,
+            "text/plain; charset=utf-8",
+            "Bad request: BadValue(RawStr(\"language\"), RawStr(\"klingon\")) (see https://api.rocket.rs/v0.4/rocket/request/enum.FormParseError.html)\n"
+        );
+    }
+
     #[test]
     fn test_invalid_serve_bad_split() {
         invoke_serve_lowlevel(
@@ -226,6 +1330,71 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_invalid_serve_bad_tz() {
+        invoke_serve_lowlevel(
+            "/services/feeder/usercalendar.ics?id=123&language=cs&tz=Europe%2FGotham",
+            Status::BadRequest,
+            "text/plain; charset=utf-8",
+            "Bad request: BadValue(RawStr(\"tz\"), RawStr(\"Europe%2FGotham\")) (see https://api.rocket.rs/v0.4/rocket/request/enum.FormParseError.html)\n"
+        );
+    }
+
+    #[test]
+    fn test_invalid_serve_bad_alarm() {
+        invoke_serve_lowlevel(
+            "/services/feeder/usercalendar.ics?id=123&language=cs&alarm=-2H",
+            Status::BadRequest,
+            "text/plain; charset=utf-8",
+            "Bad request: BadValue(RawStr(\"alarm\"), RawStr(\"-2H\")) (see https://api.rocket.rs/v0.4/rocket/request/enum.FormParseError.html)\n"
+        );
+    }
+
+    #[test]
+    fn test_invalid_serve_bad_colors() {
+        invoke_serve_lowlevel(
+            "/services/feeder/usercalendar.ics?id=123&language=cs&colors=concerts:%23ff0000",
+            Status::BadRequest,
+            "text/plain; charset=utf-8",
+            "Bad request: BadValue(RawStr(\"colors\"), RawStr(\"concerts:%23ff0000\")) (see https://api.rocket.rs/v0.4/rocket/request/enum.FormParseError.html)\n"
+        );
+    }
+
+    #[test]
+    fn test_invalid_serve_bad_uids() {
+        invoke_serve_lowlevel(
+            "/services/feeder/usercalendar.ics?id=123&language=cs&uids=v3",
+            Status::BadRequest,
+            "text/plain; charset=utf-8",
+            "Bad request: BadValue(RawStr(\"uids\"), RawStr(\"v3\")) (see https://api.rocket.rs/v0.4/rocket/request/enum.FormParseError.html)\n"
+        );
+    }
+
+    #[test]
+    fn test_invalid_serve_bad_format() {
+        invoke_serve_lowlevel(
+            "/services/feeder/usercalendar.ics?id=123&language=cs&format=pdf",
+            Status::BadRequest,
+            "text/plain; charset=utf-8",
+            "Bad request: BadValue(RawStr(\"format\"), RawStr(\"pdf\")) (see https://api.rocket.rs/v0.4/rocket/request/enum.FormParseError.html)\n"
+        );
+    }
+
+    #[test]
+    fn test_parse_ical_duration() {
+        assert_eq!(parse_ical_duration("-PT2H"), Some(Duration::hours(-2)));
+        assert_eq!(parse_ical_duration("+PT15M"), Some(Duration::minutes(15)));
+        assert_eq!(parse_ical_duration("P1D"), Some(Duration::days(1)));
+        assert_eq!(parse_ical_duration("-P2W"), Some(Duration::weeks(-2)));
+        assert_eq!(parse_ical_duration("-P1DT12H30M5S"), Some(-Duration::seconds(131_405)));
+        assert_eq!(parse_ical_duration("PT0S"), Some(Duration::zero()));
+
+        for invalid in &["", "-", "P", "PT", "P1", "PT1", "-2H", "P1H", "PT1D", "PT1M1H", "P1W1D"] {
+            assert_eq!(parse_ical_duration(invalid), None, "{} should be invalid", invalid);
+        }
+        assert_eq!(parse_ical_duration("P99999999999999999999D"), None);
+    }
+
     fn invoke_serve(path: &str, expected_ical_file: &str) {
         invoke_serve_ex(
             path,
@@ -252,6 +1421,27 @@ mod tests {
         goout_api_mock.assert();
     }
 
+    fn invoke_serve_format(
+        path: &str,
+        accept: Option<&str>,
+        expected_content_type: &str,
+        expected_file: &str,
+    ) {
+        let goout_api_path = "/services/feeder/v1/events.json?tag=liked&user=43224&page=1&language=en&source=goout.strohel.eu";
+        let goout_api_mock =
+            mock("GET", goout_api_path).with_body_from_file("test_data/events.json").create();
+
+        let client = Client::new(rocket()).unwrap();
+        let mut request = client.get(path);
+        if let Some(accept) = accept {
+            request.add_header(Header::new("Accept", accept.to_string()));
+        }
+        let expected_body = fs::read_to_string(expected_file).unwrap();
+        assert_response(request.dispatch(), Status::Ok, expected_content_type, &expected_body);
+
+        goout_api_mock.assert();
+    }
+
     fn invoke_serve_lowlevel(
         path: &str,
         expected_status: Status,
@@ -259,8 +1449,16 @@ mod tests {
         expected_body: &str,
     ) {
         let client = Client::new(rocket()).unwrap();
-        let mut response = client.get(path).dispatch();
+        let response = client.get(path).dispatch();
+        assert_response(response, expected_status, expected_content_type, expected_body);
+    }
 
+    fn assert_response(
+        mut response: LocalResponse,
+        expected_status: Status,
+        expected_content_type: &str,
+        expected_body: &str,
+    ) {
         let content_type = response.content_type().unwrap().to_string();
         let body = response.body_string().unwrap();
         // compare all at once for most descriptive failure messages by pretty_assertions
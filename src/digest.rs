@@ -0,0 +1,158 @@
+use crate::{
+    calendar::{to_calendar_request, CalendarQuery},
+    error::HandlerError,
+    generation,
+};
+use chrono::{NaiveDate, Utc};
+use rocket::{
+    get,
+    http::{Accept, ContentType, RawStr},
+    request::FormParseError,
+    response::Content,
+    FromFormValue,
+};
+
+/// Length of the period covered by a digest.
+#[derive(Clone, Copy, Debug, FromFormValue)]
+pub(in crate) enum DigestPeriod {
+    Day,
+    Week,
+}
+
+impl DigestPeriod {
+    pub(in crate) const fn days(self) -> i64 {
+        match self {
+            Self::Day => 1,
+            Self::Week => 7,
+        }
+    }
+}
+
+/// Representation of a digest.
+#[derive(Clone, Copy, Debug, FromFormValue)]
+pub(in crate) enum DigestView {
+    /// Plain text, suitable for chat messages.
+    Text,
+    /// HTML page, suitable for e-mails.
+    Html,
+}
+
+impl DigestView {
+    const fn content_type(self) -> ContentType {
+        match self {
+            Self::Text => ContentType::Plain,
+            Self::Html => ContentType::HTML,
+        }
+    }
+}
+
+/// "What's on" digest of liked schedules of user `id` in `period` (a week by default) starting
+/// on date `from` (today by default). Plain text unless `view=html` is given or HTML is preferred
+/// by the Accept header. Accepts the same filters as the calendar endpoint.
+#[get("/digest/<id>?<period>&<from>&<view>&<compat_cal_req_form..>")]
+pub(in crate) fn digest(
+    id: u64,
+    period: Option<Result<DigestPeriod, &RawStr>>,
+    from: Option<&RawStr>,
+    view: Option<Result<DigestView, &RawStr>>,
+    compat_cal_req_form: Result<CalendarQuery, FormParseError>,
+    accept: Option<&Accept>,
+) -> Result<Content<String>, HandlerError> {
+    let cal_req = to_calendar_request(compat_cal_req_form, Some(id), None)?;
+    let period = period
+        .transpose()
+        .map_err(|err| FormParseError::BadValue("period".into(), err))?
+        .unwrap_or(DigestPeriod::Week);
+    let from = match from {
+        None => Utc::now().with_timezone(&cal_req.tz).date().naive_local(),
+        Some(from) => NaiveDate::parse_from_str(from.as_str(), "%Y-%m-%d")
+            .map_err(|_| FormParseError::BadValue("from".into(), from))?,
+    };
+    let view = view
+        .transpose()
+        .map_err(|err| FormParseError::BadValue("view".into(), err))?
+        .or_else(|| {
+            accept.filter(|a| a.preferred().media_type().is_html()).map(|_| DigestView::Html)
+        })
+        .unwrap_or(DigestView::Text);
+
+    let digest_string = generation::generate_digest(&cal_req, from, period, view)?;
+    Ok(Content(view.content_type(), digest_string))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::rocket;
+    use mockito::mock;
+    use rocket::http::{Header, Status};
+    use rocket::local::Client;
+    use std::fs;
+
+    #[test]
+    fn test_digest_text() {
+        invoke_digest(
+            "/digest/43224?language=en&from=2020-03-01",
+            None,
+            "text/plain; charset=utf-8",
+            "test_data/expected_digest.txt",
+        );
+    }
+
+    #[test]
+    fn test_digest_html_accept() {
+        invoke_digest(
+            "/digest/43224?language=en&from=2020-03-01",
+            Some("text/html,application/xhtml+xml;q=0.9"),
+            "text/html; charset=utf-8",
+            "test_data/expected_digest.html",
+        );
+    }
+
+    #[test]
+    fn test_digest_day_html() {
+        invoke_digest(
+            "/digest/43224?language=en&from=2020-03-01&period=day&view=html",
+            None,
+            "text/html; charset=utf-8",
+            "test_data/expected_digest_day.html",
+        );
+    }
+
+    #[test]
+    fn test_invalid_digest_bad_from() {
+        let client = Client::new(rocket()).unwrap();
+        let mut response = client.get("/digest/43224?language=en&from=tomorrow").dispatch();
+        assert_eq!(response.status(), Status::BadRequest);
+        assert_eq!(
+            response.body_string().unwrap(),
+            "Bad request: BadValue(RawStr(\"from\"), RawStr(\"tomorrow\")) (see https://api.rocket.rs/v0.4/rocket/request/enum.FormParseError.html)\n"
+        );
+    }
+
+    fn invoke_digest(
+        path: &str,
+        accept: Option<&str>,
+        expected_content_type: &str,
+        expected_file: &str,
+    ) {
+        let goout_api_path = "/services/feeder/v1/events.json?tag=liked&user=43224&page=1&language=en&source=goout.strohel.eu";
+        let goout_api_mock =
+            mock("GET", goout_api_path).with_body_from_file("test_data/events.json").create();
+
+        let client = Client::new(rocket()).unwrap();
+        let mut request = client.get(path);
+        if let Some(accept) = accept {
+            request.add_header(Header::new("Accept", accept.to_string()));
+        }
+        let mut response = request.dispatch();
+        let content_type = response.content_type().unwrap().to_string();
+        let body = response.body_string().unwrap();
+        let expected_body = fs::read_to_string(expected_file).unwrap();
+        assert_eq!(
+            (response.status(), content_type.as_str(), body.as_str()),
+            (Status::Ok, expected_content_type, expected_body.as_str())
+        );
+
+        goout_api_mock.assert();
+    }
+}
@@ -0,0 +1,143 @@
+//! Human-readable HTML agenda of a calendar, with events grouped by day.
+
+use super::tree::{Component, Property, ValueType};
+use crate::calendar::CalendarRequest;
+use chrono::{DateTime, Duration, NaiveDate, NaiveDateTime};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use tinytemplate::TinyTemplate;
+
+const TEMPLATE_NAME: &str = "agenda";
+const TEMPLATE: &str = include_str!("../../resources/agenda.html");
+
+#[derive(Serialize)]
+struct AgendaContext {
+    language: &'static str,
+    title: &'static str,
+    description: &'static str,
+    empty: &'static str,
+    days: Vec<AgendaDay>,
+}
+
+#[derive(Serialize)]
+struct AgendaDay {
+    date: String,
+    items: Vec<AgendaItem>,
+}
+
+#[derive(Serialize)]
+struct AgendaItem {
+    time: String,
+    summary: String,
+    location: String,
+    description: String,
+    url: String,
+    cancelled: bool,
+}
+
+/// Start or end of an event as it appears in the calendar.
+#[derive(Clone, Copy, Eq, Ord, PartialEq, PartialOrd)]
+enum Moment {
+    Date(NaiveDate),
+    DateTime(NaiveDateTime), // local in the requested timezone
+}
+
+impl Moment {
+    fn date(self) -> NaiveDate {
+        match self {
+            Self::Date(date) => date,
+            Self::DateTime(datetime) => datetime.date(),
+        }
+    }
+}
+
+/// Render VEVENTs of `calendar` as an HTML page.
+pub(super) fn render(calendar: &Component, cal_req: &CalendarRequest) -> String {
+    let lang = cal_req.language;
+    let mut days: BTreeMap<NaiveDate, Vec<(Moment, AgendaItem)>> = BTreeMap::new();
+    for event in calendar.components.iter().filter(|c| c.name == "VEVENT") {
+        let start = moment(event, "DTSTART", cal_req).expect("Event without DTSTART.");
+        let end = moment(event, "DTEND", cal_req).unwrap_or(start);
+        let time = match (start, end) {
+            // events ending at midnight don't spill into the next day
+            (Moment::DateTime(start), Moment::DateTime(end))
+                if start.date() == (end - Duration::seconds(1)).date() =>
+            {
+                format!("{}–{}", start.format("%H:%M"), end.format("%H:%M"))
+            }
+            (Moment::DateTime(start), Moment::DateTime(end)) => {
+                format!("{} – {}", start.format("%H:%M"), end.format("%Y-%m-%d %H:%M"))
+            }
+            // DTEND of all-day events is exclusive
+            (Moment::Date(start), Moment::Date(end)) if end - start > Duration::days(1) => {
+                format!("{} – {}", start, end - Duration::days(1))
+            }
+            _ => lang.tr("all-day").to_string(),
+        };
+        let item = AgendaItem {
+            time,
+            summary: text(event, "SUMMARY"),
+            location: text(event, "LOCATION"),
+            description: text(event, "DESCRIPTION"),
+            url: text(event, "URL"),
+            cancelled: text(event, "STATUS") == "CANCELLED",
+        };
+        days.entry(start.date()).or_default().push((start, item));
+    }
+
+    let context = AgendaContext {
+        language: lang.code(),
+        title: lang.tr("calendar-name"),
+        description: lang.tr("calendar-description"),
+        empty: lang.tr("agenda-empty"),
+        days: days
+            .into_iter()
+            .map(|(date, mut items)| {
+                // all-day events first, then by time, stable otherwise
+                items.sort_by_key(|(start, _)| *start);
+                AgendaDay {
+                    date: date.to_string(),
+                    items: items.into_iter().map(|(_, item)| item).collect(),
+                }
+            })
+            .collect(),
+    };
+
+    let mut template = TinyTemplate::new();
+    template.add_template(TEMPLATE_NAME, TEMPLATE).expect("Agenda template should be valid.");
+    template.add_formatter("multiline", format_multiline);
+    template.render(TEMPLATE_NAME, &context).expect("Rendering agenda should not fail.")
+}
+
+fn property<'a>(component: &'a Component, name: &str) -> Option<&'a Property> {
+    component.properties.iter().find(|p| p.name == name)
+}
+
+fn text(component: &Component, name: &str) -> String {
+    property(component, name).map(|p| p.values().join(", ")).unwrap_or_default()
+}
+
+fn moment(component: &Component, name: &str, cal_req: &CalendarRequest) -> Option<Moment> {
+    let property = property(component, name)?;
+    let value = property.values().into_iter().next()?;
+    match property.value_type() {
+        ValueType::Date => NaiveDate::parse_from_str(&value, "%Y-%m-%d").ok().map(Moment::Date),
+        ValueType::DateTime => DateTime::parse_from_rfc3339(&value)
+            .ok()
+            .map(|datetime| Moment::DateTime(datetime.with_timezone(&cal_req.tz).naive_local())),
+        _ => None,
+    }
+}
+
+/// HTML-escape a string value and convert its newlines to line breaks.
+fn format_multiline(value: &Value, output: &mut String) -> tinytemplate::error::Result<()> {
+    let text = value.as_str().unwrap_or_default();
+    for (index, line) in text.split('\n').enumerate() {
+        if index > 0 {
+            output.push_str("<br>");
+        }
+        tinytemplate::escape(line, output);
+    }
+    Ok(())
+}
@@ -0,0 +1,119 @@
+//! Our own JSON representation of schedules, independent of the GoOut wire format.
+
+use super::{DateTime, Schedule};
+use serde::Serialize;
+
+#[derive(Serialize)]
+struct SchedulesPage<'a> {
+    page: usize,
+    per_page: usize,
+    total: usize,
+    has_next: bool,
+    schedules: Vec<ScheduleJson<'a>>,
+}
+
+#[derive(Serialize)]
+struct ScheduleJson<'a> {
+    id: u64,
+    event_id: u64,
+    url: &'a str,
+    cancelled: bool,
+    start: &'a DateTime,
+    end: &'a DateTime, // exclusive
+    uploaded_on: &'a DateTime,
+    hour_ignored: bool,
+    long_term: bool,
+    permanent: bool,
+    pricing: &'a str,
+    currency: &'a str,
+    event: EventJson<'a>,
+    venue: VenueJson<'a>,
+    performers: Vec<PerformerJson<'a>>,
+}
+
+#[derive(Serialize)]
+struct EventJson<'a> {
+    name: &'a str,
+    text: &'a str,
+    categories: Vec<CategoryJson<'a>>,
+    image_url: Option<String>,
+}
+
+#[derive(Serialize)]
+struct CategoryJson<'a> {
+    name: &'a str,
+    kind: String, // lowercase, e.g. "concerts"
+}
+
+#[derive(Serialize)]
+struct VenueJson<'a> {
+    id: u64,
+    name: &'a str,
+    address: &'a str,
+    city: &'a str,
+    country: &'a str,
+    latitude: f64,
+    longitude: f64,
+}
+
+#[derive(Serialize)]
+struct PerformerJson<'a> {
+    name: &'a str,
+    tags: &'a [String],
+}
+
+/// Serialise `page` (1-based) of `schedules` with `per_page` schedules per page.
+pub(super) fn render(schedules: &[Schedule], page: usize, per_page: usize) -> String {
+    // page comes from the query and can be arbitrarily large, saturate instead of overflowing
+    let page_schedules = schedules.iter().skip((page - 1).saturating_mul(per_page)).take(per_page);
+    let schedules_page = SchedulesPage {
+        page,
+        per_page,
+        total: schedules.len(),
+        has_next: page.saturating_mul(per_page) < schedules.len(),
+        schedules: page_schedules.map(schedule_json).collect(),
+    };
+    serde_json::to_string(&schedules_page).expect("Serialising schedules should not fail.")
+}
+
+fn schedule_json(schedule: &Schedule) -> ScheduleJson<'_> {
+    let (event, venue) = (&schedule.event, &schedule.venue);
+    ScheduleJson {
+        id: schedule.id,
+        event_id: schedule.event_id,
+        url: &schedule.url,
+        cancelled: schedule.cancelled,
+        start: &schedule.start,
+        end: &schedule.end,
+        uploaded_on: &schedule.uploaded_on,
+        hour_ignored: schedule.hour_ignored,
+        long_term: schedule.is_long_term,
+        permanent: schedule.permanent,
+        pricing: &schedule.pricing,
+        currency: &schedule.currency,
+        event: EventJson {
+            name: &event.name,
+            text: &event.text,
+            categories: event
+                .categories
+                .values()
+                .map(|c| CategoryJson { name: &c.name, kind: c.kind.to_lowercase() })
+                .collect(),
+            image_url: event.main_image.as_ref().map(|image| image.url()),
+        },
+        venue: VenueJson {
+            id: schedule.venue_id,
+            name: &venue.name,
+            address: &venue.address,
+            city: &venue.city,
+            country: &venue.locality.country.name,
+            latitude: venue.latitude,
+            longitude: venue.longitude,
+        },
+        performers: schedule
+            .performers
+            .iter()
+            .map(|p| PerformerJson { name: &p.name, tags: &p.tags })
+            .collect(),
+    }
+}
@@ -0,0 +1,120 @@
+//! "What's on" digest of schedules within a period, as plain text or HTML.
+
+use super::ical::{get_summary, is_all_day, EventPhase, ScheduleHelper};
+use super::Schedule;
+use crate::calendar::CalendarRequest;
+use crate::digest::{DigestPeriod, DigestView};
+use chrono::{Duration, NaiveDate};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use tinytemplate::{format_unescaped, TinyTemplate};
+
+const TEMPLATE_NAME: &str = "digest";
+const TEXT_TEMPLATE: &str = include_str!("../../resources/digest.txt");
+const HTML_TEMPLATE: &str = include_str!("../../resources/digest.html");
+
+#[derive(Serialize)]
+struct DigestContext {
+    language: &'static str,
+    title: &'static str,
+    first_day: String,
+    last_day: String,
+    longterm_title: &'static str,
+    empty: &'static str,
+    days: Vec<DigestDay>,
+    longterm: Vec<DigestItem>,
+}
+
+#[derive(Serialize)]
+struct DigestDay {
+    date: String,
+    items: Vec<DigestItem>,
+}
+
+#[derive(Serialize)]
+struct DigestItem {
+    time: String,
+    summary: String,
+    location: String,
+    url: String,
+}
+
+/// Render digest of `schedules` (sorted by start) for `period` beginning on date `from`.
+pub(super) fn render(
+    schedules: &[Schedule],
+    from: NaiveDate,
+    period: DigestPeriod,
+    view: DigestView,
+    cal_req: &CalendarRequest,
+) -> String {
+    let (lang, tz) = (cal_req.language, cal_req.tz);
+    let to = from + Duration::days(period.days()); // exclusive
+
+    let mut days: BTreeMap<NaiveDate, Vec<DigestItem>> = BTreeMap::new();
+    let mut longterm: Vec<(EventPhase, DigestItem)> = Vec::new();
+    for schedule in schedules {
+        let (start_date, end_date) = (schedule.start_date(tz), schedule.end_date(tz));
+        if schedule.is_long_term {
+            if start_date >= to || end_date <= from {
+                continue;
+            }
+            // summarise the same way as the aggregate long-term mode does
+            let phase = EventPhase::of(schedule, from, to, tz);
+            let item = DigestItem {
+                time: format!("{} - {}", start_date, schedule.last_date(tz)),
+                summary: format!("{}{}", phase.prefix(lang), get_summary(schedule, lang)),
+                ..digest_item(schedule, String::new(), cal_req)
+            };
+            longterm.push((phase, item));
+        } else if start_date >= from && start_date < to {
+            let time = if is_all_day(schedule) {
+                lang.tr("all-day").to_string()
+            } else {
+                let (start, end) =
+                    (schedule.start.with_timezone(&tz), schedule.end.with_timezone(&tz));
+                format!("{}–{}", start.format("%H:%M"), end.format("%H:%M"))
+            };
+            days.entry(start_date).or_default().push(digest_item(schedule, time, cal_req));
+        }
+    }
+    // stable, so schedules with the same phase stay sorted by start
+    longterm.sort_by_key(|(phase, _)| *phase);
+
+    let context = DigestContext {
+        language: lang.code(),
+        title: match period {
+            DigestPeriod::Day => lang.tr("digest-day"),
+            DigestPeriod::Week => lang.tr("digest-week"),
+        },
+        first_day: from.to_string(),
+        last_day: (to - Duration::days(1)).to_string(),
+        longterm_title: lang.tr("digest-longterm"),
+        empty: lang.tr("agenda-empty"),
+        days: days
+            .into_iter()
+            .map(|(date, items)| DigestDay { date: date.to_string(), items })
+            .collect(),
+        longterm: longterm.into_iter().map(|(_, item)| item).collect(),
+    };
+
+    let mut template = TinyTemplate::new();
+    let source = match view {
+        DigestView::Text => {
+            template.set_default_formatter(&format_unescaped);
+            TEXT_TEMPLATE
+        }
+        DigestView::Html => HTML_TEMPLATE,
+    };
+    template.add_template(TEMPLATE_NAME, source).expect("Digest template should be valid.");
+    template.render(TEMPLATE_NAME, &context).expect("Rendering digest should not fail.")
+}
+
+fn digest_item(schedule: &Schedule, time: String, cal_req: &CalendarRequest) -> DigestItem {
+    let venue = &schedule.venue;
+    DigestItem {
+        time,
+        summary: get_summary(schedule, cal_req.language),
+        location: format!("{}, {}", venue.name, venue.city),
+        url: schedule.url.clone(),
+    }
+}
@@ -0,0 +1,89 @@
+//! Feeds (RSS and Atom) of schedules, one item per schedule.
+
+use super::ical::{get_description, get_summary, OptionalDescFields, GOOUT_URL};
+use super::{escape_xml, Schedule};
+use crate::calendar::CalendarRequest;
+use chrono::{SecondsFormat, Utc};
+use std::fmt::Write;
+
+const ATOM_NAMESPACE: &str = "http://www.w3.org/2005/Atom";
+/// Authority for tag URIs (RFC 4151) used as Atom ids, the domain of the demo instance.
+const TAG_AUTHORITY: &str = "goout.strohel.eu,2020";
+/// Author of Atom entries (which must have one), given once for the whole feed.
+const FEED_AUTHOR: &str = "GoOut";
+
+pub(super) fn render_rss(schedules: &[Schedule], cal_req: &CalendarRequest) -> String {
+    let lang = cal_req.language;
+    let mut output = String::new();
+    let expect_msg = "Writing to String should not fail.";
+    writeln!(output, "<?xml version=\"1.0\" encoding=\"utf-8\"?>").expect(expect_msg);
+    write!(
+        output,
+        "<rss version=\"2.0\"><channel><title>{}</title><link>{}</link>\
+         <description>{}</description><language>{}</language>",
+        escape_xml(lang.tr("calendar-name")),
+        GOOUT_URL,
+        escape_xml(lang.tr("calendar-description")),
+        lang.code()
+    )
+    .expect(expect_msg);
+    for schedule in schedules {
+        write!(
+            output,
+            "<item><title>{}</title><link>{}</link><description>{}</description>\
+             <guid isPermaLink=\"false\">{}</guid><pubDate>{}</pubDate></item>",
+            escape_xml(&get_summary(schedule, lang)),
+            escape_xml(&schedule.url),
+            escape_xml(&get_description(schedule, OptionalDescFields::default())),
+            tag_uri("schedule", schedule.id),
+            schedule.uploaded_on.to_rfc2822()
+        )
+        .expect(expect_msg);
+    }
+    writeln!(output, "</channel></rss>").expect(expect_msg);
+    output
+}
+
+pub(super) fn render_atom(schedules: &[Schedule], cal_req: &CalendarRequest) -> String {
+    let lang = cal_req.language;
+    // Atom requires the updated element, fall back to now for empty feeds.
+    let updated =
+        schedules.iter().map(|s| s.uploaded_on.with_timezone(&Utc)).max().unwrap_or_else(Utc::now);
+
+    let mut output = String::new();
+    let expect_msg = "Writing to String should not fail.";
+    writeln!(output, "<?xml version=\"1.0\" encoding=\"utf-8\"?>").expect(expect_msg);
+    write!(
+        output,
+        "<feed xmlns=\"{}\" xml:lang=\"{}\"><id>{}</id><title>{}</title><subtitle>{}</subtitle>\
+         <updated>{}</updated><link href=\"{}\"/><author><name>{}</name></author>",
+        ATOM_NAMESPACE,
+        lang.code(),
+        tag_uri("user", cal_req.id),
+        escape_xml(lang.tr("calendar-name")),
+        escape_xml(lang.tr("calendar-description")),
+        updated.to_rfc3339_opts(SecondsFormat::Secs, true),
+        GOOUT_URL,
+        FEED_AUTHOR
+    )
+    .expect(expect_msg);
+    for schedule in schedules {
+        write!(
+            output,
+            "<entry><id>{}</id><title>{}</title><updated>{}</updated><link href=\"{}\"/>\
+             <content type=\"text\">{}</content></entry>",
+            tag_uri("schedule", schedule.id),
+            escape_xml(&get_summary(schedule, lang)),
+            schedule.uploaded_on.to_rfc3339(),
+            escape_xml(&schedule.url),
+            escape_xml(&get_description(schedule, OptionalDescFields::default()))
+        )
+        .expect(expect_msg);
+    }
+    writeln!(output, "</feed>").expect(expect_msg);
+    output
+}
+
+fn tag_uri(kind: &str, id: u64) -> String {
+    format!("tag:{}:{}/{}", TAG_AUTHORITY, kind, id)
+}
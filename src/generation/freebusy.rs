@@ -0,0 +1,94 @@
+//! Free/busy time (VFREEBUSY) of a user, which tells when the user is busy but not with what.
+
+use super::ical::{is_all_day, start_of_day, wrap_in_calendar, ScheduleHelper};
+use super::Schedule;
+use crate::calendar::CalendarRequest;
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use chrono_tz::Tz;
+use icalendar::Property;
+
+const ICAL_UTC_FORMAT: &str = "%Y%m%dT%H%M%SZ";
+
+/// Period when `schedule` makes the user busy. All-day schedules, whose times are made up by GoOut,
+/// take whole days in `tz`.
+fn busy_period(schedule: &Schedule, tz: Tz) -> (DateTime<Utc>, DateTime<Utc>) {
+    if is_all_day(schedule) {
+        let start_date = schedule.start_date(tz);
+        let end_date = start_date + Duration::days(schedule.days(tz));
+        (
+            start_of_day(start_date, tz).with_timezone(&Utc),
+            start_of_day(end_date, tz).with_timezone(&Utc),
+        )
+    } else {
+        (schedule.start.with_timezone(&Utc), schedule.end.with_timezone(&Utc))
+    }
+}
+
+/// Render VCALENDAR with a single VFREEBUSY built from timed `schedules`, all-day and long-term
+/// ones are only included when requested. Window is given by `after` and `before` dates.
+pub(super) fn render(schedules: &[Schedule], cal_req: &CalendarRequest) -> String {
+    let tz = cal_req.tz;
+    // GoOut expects `after` as date, don't fail if some other value gets through to it
+    let window_start = cal_req
+        .after
+        .as_ref()
+        .and_then(|after| NaiveDate::parse_from_str(after, "%Y-%m-%d").ok())
+        .map(|date| start_of_day(date, tz).with_timezone(&Utc));
+    let window_end = cal_req.before.map(|date| start_of_day(date, tz).with_timezone(&Utc));
+
+    let busy_schedules: Vec<&Schedule> = schedules
+        .iter()
+        .filter(|s| !s.cancelled)
+        .filter(|s| cal_req.busy_allday || !s.hour_ignored)
+        .filter(|s| cal_req.busy_longterm || !s.is_long_term)
+        .collect();
+    let mut periods: Vec<(DateTime<Utc>, DateTime<Utc>)> = busy_schedules
+        .iter()
+        .map(|s| {
+            let (start, end) = busy_period(s, tz);
+            (window_start.map_or(start, |w| w.max(start)), window_end.map_or(end, |w| w.min(end)))
+        })
+        .filter(|(start, end)| start < end)
+        .collect();
+    periods.sort();
+
+    // RFC 5545 says FREEBUSY periods should be merged where they overlap
+    let mut merged: Vec<(DateTime<Utc>, DateTime<Utc>)> = Vec::new();
+    for (start, end) in periods {
+        match merged.last_mut() {
+            Some((_, last_end)) if start <= *last_end => *last_end = end.max(*last_end),
+            _ => merged.push((start, end)),
+        }
+    }
+
+    // Like other events, DTSTAMP is the last revision time, so that the output is stable.
+    let dtstamp = busy_schedules
+        .iter()
+        .map(|s| s.uploaded_on.with_timezone(&Utc))
+        .max()
+        .unwrap_or_else(Utc::now);
+    let mut properties = vec![
+        Property::new("DTSTAMP", &dtstamp.format(ICAL_UTC_FORMAT).to_string()),
+        Property::new("UID", &format!("freebusy/User#{}@goout.net", cal_req.id)),
+    ];
+    if let Some(window_start) = window_start {
+        properties
+            .push(Property::new("DTSTART", &window_start.format(ICAL_UTC_FORMAT).to_string()));
+    }
+    if let Some(window_end) = window_end {
+        properties.push(Property::new("DTEND", &window_end.format(ICAL_UTC_FORMAT).to_string()));
+    }
+    for (start, end) in merged {
+        let period = format!("{}/{}", start.format(ICAL_UTC_FORMAT), end.format(ICAL_UTC_FORMAT));
+        properties.push(Property::new("FREEBUSY", &period).add_parameter("FBTYPE", "BUSY").done());
+    }
+
+    let mut component = String::new();
+    let expect_msg = "Writing to String should not fail.";
+    component.push_str("BEGIN:VFREEBUSY\r\n");
+    for property in properties.iter() {
+        property.fmt_write(&mut component).expect(expect_msg);
+    }
+    component.push_str("END:VFREEBUSY\r\n");
+    wrap_in_calendar(&component, cal_req)
+}
@@ -0,0 +1,84 @@
+//! GeoJSON (RFC 7946) map of venues of schedules, one Point feature per venue.
+
+use super::{DateTime, Schedule, Venue};
+use serde::Serialize;
+
+#[derive(Serialize)]
+struct FeatureCollection<'a> {
+    #[serde(rename = "type")]
+    type_: &'static str,
+    features: Vec<Feature<'a>>,
+}
+
+#[derive(Serialize)]
+struct Feature<'a> {
+    #[serde(rename = "type")]
+    type_: &'static str,
+    geometry: Point,
+    properties: VenueProperties<'a>,
+}
+
+#[derive(Serialize)]
+struct Point {
+    #[serde(rename = "type")]
+    type_: &'static str,
+    coordinates: [f64; 2], // longitude first
+}
+
+#[derive(Serialize)]
+struct VenueProperties<'a> {
+    name: &'a str,
+    address: &'a str,
+    city: &'a str,
+    country: &'a str,
+    schedules: Vec<ScheduleProperties<'a>>,
+}
+
+#[derive(Serialize)]
+struct ScheduleProperties<'a> {
+    id: u64,
+    event: &'a str,
+    start: &'a DateTime,
+    end: &'a DateTime, // exclusive
+    url: &'a str,
+    cancelled: bool,
+}
+
+/// Render venues of `schedules` as a FeatureCollection, in order of their first schedule.
+pub(super) fn render(schedules: &[Schedule]) -> String {
+    let mut features: Vec<Feature<'_>> = Vec::new();
+    for schedule in schedules {
+        let venue = &schedule.venue;
+        let properties = ScheduleProperties {
+            id: schedule.id,
+            event: &schedule.event.name,
+            start: &schedule.start,
+            end: &schedule.end,
+            url: &schedule.url,
+            cancelled: schedule.cancelled,
+        };
+        // GoOut doesn't give us venue IDs, identify venues by their name and position instead.
+        match features.iter_mut().find(|f| is_same_venue(f, venue)) {
+            Some(feature) => feature.properties.schedules.push(properties),
+            None => features.push(Feature {
+                type_: "Feature",
+                geometry: Point { type_: "Point", coordinates: [venue.longitude, venue.latitude] },
+                properties: VenueProperties {
+                    name: &venue.name,
+                    address: &venue.address,
+                    city: &venue.city,
+                    country: &venue.locality.country.name,
+                    schedules: vec![properties],
+                },
+            }),
+        }
+    }
+    let collection = FeatureCollection { type_: "FeatureCollection", features };
+    serde_json::to_string(&collection).expect("Serialising GeoJSON should not fail.")
+}
+
+#[allow(clippy::float_cmp)] // coordinates are deserialised, not computed
+fn is_same_venue(feature: &Feature<'_>, venue: &Venue) -> bool {
+    feature.properties.name == venue.name
+        && feature.geometry.coordinates == [venue.longitude, venue.latitude]
+}
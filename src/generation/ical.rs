@@ -1,41 +1,218 @@
-use super::Schedule;
-use crate::calendar::{CalendarRequest, LongtermHandling};
+use super::{distance_km, passes_timed_filters, sequence, Category, DateTime, Schedule};
+use crate::calendar::{
+    AlarmTrigger, AlldayHandling, CalendarRequest, CategoryColors, LongtermHandling,
+    MultidayHandling, PermanentHandling, RepeatHandling, SamedayHandling, UidScheme,
+};
+use crate::l10n::Language;
 use bitflags::bitflags;
-use chrono::{naive::MIN_DATE, Duration, NaiveDate, TimeZone, Utc};
-use icalendar::{Component, Event as IcalEvent};
+use chrono::{
+    naive::MIN_DATE, Date, Datelike, Duration, NaiveDate, NaiveTime, Offset, TimeZone, Utc,
+};
+use chrono_tz::Tz;
+use fnv::FnvHasher;
+use icalendar::{Component, Event as IcalEvent, Property};
 use std::collections::{BTreeMap, HashSet};
+use std::fmt::{self, Write};
+use std::hash::Hasher;
+use std::ops::{Deref, DerefMut};
 use std::rc::Rc;
 
+/// VEVENT with its VALARMs, which icalendar cannot nest in events. Dereferences to the event.
+#[derive(Debug, Default)]
+pub(super) struct CalendarEvent {
+    event: IcalEvent,
+    alarms: Vec<Alarm>,
+}
+
+impl CalendarEvent {
+    fn new() -> Self {
+        Self::default()
+    }
+
+    pub(super) fn fmt_write<W: fmt::Write>(&self, out: &mut W) -> fmt::Result {
+        let mut event = String::new();
+        self.event.fmt_write(&mut event)?;
+        let end = "END:VEVENT\r\n";
+        let body = event.strip_suffix(end).expect("Serialised event should end by END:VEVENT.");
+        out.write_str(body)?;
+        for alarm in &self.alarms {
+            alarm.fmt_write(out)?;
+        }
+        out.write_str(end)
+    }
+}
+
+impl Deref for CalendarEvent {
+    type Target = IcalEvent;
+
+    fn deref(&self) -> &IcalEvent {
+        &self.event
+    }
+}
+
+impl DerefMut for CalendarEvent {
+    fn deref_mut(&mut self) -> &mut IcalEvent {
+        &mut self.event
+    }
+}
+
+impl fmt::Display for CalendarEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.fmt_write(f)
+    }
+}
+
+/// VALARM component displaying `description` at `trigger`.
+#[derive(Debug)]
+struct Alarm {
+    trigger: Property,
+    description: String,
+}
+
+impl Alarm {
+    fn fmt_write<W: fmt::Write>(&self, out: &mut W) -> fmt::Result {
+        out.write_str("BEGIN:VALARM\r\n")?;
+        Property::new("ACTION", "DISPLAY").fmt_write(out)?;
+        self.trigger.fmt_write(out)?;
+        Property::new("DESCRIPTION", &self.description).fmt_write(out)?;
+        out.write_str("END:VALARM\r\n")
+    }
+}
+
+pub(super) const GOOUT_URL: &str = "https://goout.net/";
+const GOOUT_ICON_URL: &str = "https://goout.net/favicon.ico";
+/// Number of weeks rendered by the weekly long-term mode, so that never-ending exhibitions don't
+/// produce hundreds of entries.
+const WEEKLY_HORIZON_WEEKS: i64 = 26;
+
 pub(super) fn generate_events(
     schedules: Vec<Schedule>,
     cal_req: &CalendarRequest,
-) -> Vec<IcalEvent> {
-    let language: &str = &cal_req.language;
+) -> Vec<CalendarEvent> {
+    let schedules = match cal_req.permanent {
+        PermanentHandling::Preserve => schedules,
+        PermanentHandling::Skip => schedules.into_iter().filter(|s| !s.permanent).collect(),
+        PermanentHandling::Once => {
+            schedules.into_iter().map(|s| permanent_once(s, cal_req)).collect()
+        }
+    };
+    let schedules = match cal_req.allday {
+        AlldayHandling::Preserve | AlldayHandling::Transparent => schedules,
+        AlldayHandling::Timed => schedules.into_iter().map(|s| allday_timed(s, cal_req)).collect(),
+    };
+    let (mut events, schedules) = generate_events_multiday(schedules, cal_req);
+    let (sameday_events, schedules) = match cal_req.samedays {
+        SamedayHandling::Separate => (Vec::new(), schedules),
+        SamedayHandling::Merge => generate_events_sameday(schedules, cal_req),
+    };
+    events.extend(sameday_events);
+    let (recurring_events, schedules) = match cal_req.repeats {
+        RepeatHandling::Separate => (Vec::new(), schedules),
+        RepeatHandling::Collapse => generate_events_recurring(schedules, cal_req),
+    };
+    events.extend(recurring_events);
+    events.extend(match cal_req.longterm {
+        LongtermHandling::Preserve => generate_events_preserve(schedules, cal_req),
+        LongtermHandling::Split => generate_events_split(schedules, cal_req),
+        LongtermHandling::Aggregate => generate_events_aggregate(schedules, cal_req),
+        LongtermHandling::LastChance => generate_events_lastchance(schedules, cal_req),
+        LongtermHandling::Weekly => generate_events_weekly(schedules, cal_req),
+    });
+    events
+}
+
+/// Serialise VCALENDAR with `events`. We don't use icalendar's Calendar, because it doesn't support
+/// custom calendar properties.
+pub(super) fn render_calendar(events: &[CalendarEvent], cal_req: &CalendarRequest) -> String {
+    let mut components = String::new();
+    for event in events {
+        event.fmt_write(&mut components).expect("Writing to String should not fail.");
+    }
+    wrap_in_calendar(&components, cal_req)
+}
+
+/// Serialise VCALENDAR around already serialised `components`.
+pub(super) fn wrap_in_calendar(components: &str, cal_req: &CalendarRequest) -> String {
+    let lang = cal_req.language;
+    let properties = [
+        Property::new("VERSION", "2.0"),
+        Property::new("PRODID", "ICALENDAR-RS"),
+        Property::new("CALSCALE", "GREGORIAN"),
+        // RFC 7986 properties, with the X-WR-CALNAME de-facto standard for older clients
+        Property::new("NAME", lang.tr("calendar-name")),
+        Property::new("X-WR-CALNAME", lang.tr("calendar-name")),
+        Property::new("DESCRIPTION", lang.tr("calendar-description")),
+        Property::new("URL", GOOUT_URL),
+        Property::new("IMAGE", GOOUT_ICON_URL).add_parameter("VALUE", "URI").done(),
+    ];
 
-    match cal_req.longterm {
-        LongtermHandling::Preserve => generate_events_preserve(schedules, language),
-        LongtermHandling::Split => generate_events_split(schedules, language),
-        LongtermHandling::Aggregate => generate_events_aggregate(schedules, language),
+    let mut output = String::new();
+    let expect_msg = "Writing to String should not fail.";
+    write!(output, "BEGIN:VCALENDAR\r\n").expect(expect_msg);
+    for property in properties.iter() {
+        property.fmt_write(&mut output).expect(expect_msg);
     }
+    output.push_str(components);
+    write!(output, "END:VCALENDAR\r\n").expect(expect_msg);
+    output
 }
 
-trait ScheduleHelper {
-    fn start_date(&self) -> NaiveDate;
-    fn end_date(&self) -> NaiveDate;
+pub(super) trait ScheduleHelper {
+    fn start_date(&self, tz: Tz) -> NaiveDate;
+    fn end_date(&self, tz: Tz) -> NaiveDate;
+    /// Last day of a schedule whose exclusive end is at midnight in `tz`, as shown to users.
+    fn last_date(&self, tz: Tz) -> NaiveDate;
+    /// Number of calendar days in `tz` the schedule extends into.
+    fn days(&self, tz: Tz) -> i64;
 }
 
 impl ScheduleHelper for Schedule {
-    fn start_date(&self) -> NaiveDate {
-        self.start.naive_local().date()
+    fn start_date(&self, tz: Tz) -> NaiveDate {
+        self.start.with_timezone(&tz).naive_local().date()
     }
 
-    fn end_date(&self) -> NaiveDate {
-        self.end.naive_local().date()
+    fn end_date(&self, tz: Tz) -> NaiveDate {
+        self.end.with_timezone(&tz).naive_local().date()
+    }
+
+    fn last_date(&self, tz: Tz) -> NaiveDate {
+        (self.end_date(tz) - Duration::days(1)).max(self.start_date(tz))
+    }
+
+    fn days(&self, tz: Tz) -> i64 {
+        // end is exclusive
+        let last = (self.end - Duration::seconds(1)).with_timezone(&tz).naive_local().date();
+        (last - self.start_date(tz)).num_days().max(0) + 1
     }
 }
 
+/// Return the instant of `time` on `date` in `tz`, or the first one after if `time` falls into a DST
+/// gap.
+pub(super) fn local_datetime(date: NaiveDate, time: NaiveTime, tz: Tz) -> DateTime {
+    let local = (0..24)
+        .find_map(|hour| {
+            tz.from_local_datetime(&(date.and_time(time) + Duration::hours(hour))).earliest()
+        })
+        .expect("Every day should have at least one valid hour.");
+    local.with_timezone(&local.offset().fix())
+}
+
+/// Return the first instant of `date` in `tz`. This is usually midnight, but some timezones switch
+/// DST at midnight, in which case the day starts later.
+pub(super) fn start_of_day(date: NaiveDate, tz: Tz) -> DateTime {
+    let local_start = (0..24)
+        .find_map(|hour| tz.from_local_datetime(&date.and_hms(hour, 0, 0)).earliest())
+        .expect("Every day should have at least one valid hour.");
+    local_start.with_timezone(&local_start.offset().fix())
+}
+
+fn to_ical_date(date: NaiveDate) -> Date<Utc> {
+    // icalendar currently needs Date (with timezone), but doesn't actually use the TZ. Convert.
+    Utc.from_utc_date(&date)
+}
+
 #[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
-enum EventPhase {
+pub(super) enum EventPhase {
     // order of definition here prescribes order in which the entries appear in lists
     Begin,
     BeginEnd,
@@ -44,55 +221,198 @@ enum EventPhase {
 }
 
 impl EventPhase {
-    fn prefix(self, lang: &str) -> &'static str {
-        // TODO: poor man's localization
-        match (self, lang) {
-            (EventPhase::Begin, "cs") => "Začátek: ",
-            (EventPhase::Begin, _) => "Begin: ",
-            (EventPhase::BeginEnd, "cs") => "Začátek a konec: ",
-            (EventPhase::BeginEnd, _) => "Begin and end: ",
-            (EventPhase::End, "cs") => "Konec: ",
-            (EventPhase::End, _) => "End: ",
-            (EventPhase::Continued, "cs") => "Pokračující: ",
-            (EventPhase::Continued, _) => "Continued: ",
-        }
-    }
-
-    fn format_count(self, count: usize, _lang: &str) -> String {
-        // TODO: localise
+    /// Phase of long-term `schedule` within the period from `start` to `end` (exclusive) date.
+    pub(super) fn of(schedule: &Schedule, start: NaiveDate, end: NaiveDate, tz: Tz) -> Self {
+        match (schedule.start_date(tz) >= start, schedule.end_date(tz) <= end) {
+            (true, true) => EventPhase::BeginEnd,
+            (true, false) => EventPhase::Begin,
+            (false, true) => EventPhase::End,
+            (false, false) => EventPhase::Continued,
+        }
+    }
+
+    pub(super) fn prefix(self, lang: Language) -> &'static str {
+        match self {
+            EventPhase::Begin => lang.tr("phase-begin"),
+            EventPhase::BeginEnd => lang.tr("phase-begin-end"),
+            EventPhase::End => lang.tr("phase-end"),
+            EventPhase::Continued => lang.tr("phase-continued"),
+        }
+    }
+
+    fn format_count(self, count: usize, lang: Language) -> String {
         match self {
-            EventPhase::Begin => format!("{} beginning", count),
-            EventPhase::End => format!("{} ending", count),
-            EventPhase::Continued => format!("{} continued", count),
+            EventPhase::Begin => lang.tr_count("count-begin", count),
+            EventPhase::End => lang.tr_count("count-end", count),
+            EventPhase::Continued => lang.tr_count("count-continued", count),
             EventPhase::BeginEnd => unimplemented!("should not be used"),
         }
     }
 }
 
-fn generate_events_preserve(schedules: Vec<Schedule>, language: &str) -> Vec<IcalEvent> {
-    schedules.iter().map(|s| create_ical_event(s, language)).collect()
+/// Day of a long-term schedule that gets its own event in the split mode.
+#[derive(Clone, Copy, Debug)]
+enum SplitDay {
+    First,
+    Last,
+}
+
+impl SplitDay {
+    const fn phase(self) -> EventPhase {
+        match self {
+            SplitDay::First => EventPhase::Begin,
+            SplitDay::Last => EventPhase::End,
+        }
+    }
+
+    /// Part of UIDs of the split events that identifies the day.
+    const fn uid_part(self) -> &'static str {
+        match self {
+            SplitDay::First => "begin",
+            SplitDay::Last => "end",
+        }
+    }
+}
+
+/// Point of an event to which an alarm is related.
+#[derive(Clone, Copy, Debug)]
+enum AlarmRelated {
+    Start,
+    /// End of the last day of an (all-day) event.
+    LastDay,
 }
 
-fn generate_events_split(schedules: Vec<Schedule>, language: &str) -> Vec<IcalEvent> {
-    let mut events: Vec<IcalEvent> = Vec::new();
+/// Shrink a permanent `schedule` to an all-day schedule on the `after` date (today if not given),
+/// or on its first day if it starts later, so that it appears in the calendar once. Other schedules
+/// are unchanged.
+fn permanent_once(mut schedule: Schedule, cal_req: &CalendarRequest) -> Schedule {
+    if !schedule.permanent {
+        return schedule;
+    }
+    let tz = cal_req.tz;
+    // GoOut expects `after` as date, don't fail if some other value gets through to it
+    let first_day = cal_req
+        .after
+        .as_ref()
+        .and_then(|after| NaiveDate::parse_from_str(after, "%Y-%m-%d").ok())
+        .unwrap_or_else(|| Utc::now().with_timezone(&tz).date().naive_local());
+    let start_date = schedule.start_date(tz);
+    let last_date = start_date + Duration::days(schedule.days(tz) - 1);
+    let date = first_day.max(start_date).min(last_date);
+    schedule.start = start_of_day(date, tz);
+    schedule.end = start_of_day(date + Duration::days(1), tz);
+    // not long-term anymore so that long-term modes leave it alone, but still all-day
+    schedule.is_long_term = false;
+    schedule.hour_ignored = true;
+    schedule
+}
+
+/// Give an hour-ignored, not long-term `schedule` the default time of day on its first day and the
+/// default duration on its last day, generate_events_multiday() then splits it into one occurrence
+/// per day. Other schedules are unchanged.
+fn allday_timed(mut schedule: Schedule, cal_req: &CalendarRequest) -> Schedule {
+    if !schedule.hour_ignored || schedule.is_long_term {
+        return schedule;
+    }
+    let tz = cal_req.tz;
+    let start_date = schedule.start_date(tz);
+    let last_date = start_date + Duration::days(schedule.days(tz) - 1);
+    schedule.start = local_datetime(start_date, cal_req.allday_time, tz);
+    schedule.end = local_datetime(last_date, cal_req.allday_time, tz) + cal_req.allday_duration;
+    schedule.hour_ignored = false;
+    schedule.approximate_time = true;
+    schedule
+}
+
+fn generate_events_preserve(
+    schedules: Vec<Schedule>,
+    cal_req: &CalendarRequest,
+) -> Vec<CalendarEvent> {
+    schedules.iter().map(|s| create_ical_event(s, &schedule_uid(s), cal_req)).collect()
+}
+
+fn generate_events_split(
+    schedules: Vec<Schedule>,
+    cal_req: &CalendarRequest,
+) -> Vec<CalendarEvent> {
+    let (language, tz) = (cal_req.language, cal_req.tz);
+    let mut events: Vec<CalendarEvent> = Vec::new();
+    let filters = &cal_req.filters;
     for schedule in schedules {
         if schedule.is_long_term {
-            let mut first_day_schedule = schedule.clone();
-            first_day_schedule.id = 1_000_000_000_000 + schedule.id;
-            Rc::make_mut(&mut first_day_schedule.event).name =
-                format!("{}{}", EventPhase::Begin.prefix(language), schedule.event.name);
-            first_day_schedule.end = schedule.start.date().and_hms(0, 0, 0) + Duration::days(1);
-            events.push(create_ical_event(&first_day_schedule, language));
-
-            let mut last_day_schedule = schedule.clone();
-            last_day_schedule.id = 2_000_000_000_000 + schedule.id;
-            Rc::make_mut(&mut last_day_schedule.event).name =
-                format!("{}{}", EventPhase::End.prefix(language), schedule.event.name);
-            last_day_schedule.start = schedule.end.date().and_hms(0, 0, 0) - Duration::days(1);
-            events.push(create_ical_event(&last_day_schedule, language));
+            if filters.allows_day(schedule.start_date(tz)) {
+                let mut first_day_schedule = schedule.clone();
+                Rc::make_mut(&mut first_day_schedule.event).name =
+                    format!("{}{}", SplitDay::First.phase().prefix(language), schedule.event.name);
+                // add days to date and not to time so that DST transitions don't shift the boundary
+                first_day_schedule.end =
+                    start_of_day(schedule.start_date(tz) + Duration::days(1), tz);
+                let uid = split_uid(&schedule, SplitDay::First, cal_req.uids);
+                events.push(create_ical_event(&first_day_schedule, &uid, cal_req));
+            }
+
+            let last_date = schedule.end_date(tz) - Duration::days(1);
+            if filters.allows_day(last_date) {
+                let mut last_day_schedule = schedule.clone();
+                Rc::make_mut(&mut last_day_schedule.event).name =
+                    format!("{}{}", SplitDay::Last.phase().prefix(language), schedule.event.name);
+                last_day_schedule.start = start_of_day(last_date, tz);
+                let uid = split_uid(&schedule, SplitDay::Last, cal_req.uids);
+                let mut ical_event = create_ical_event(&last_day_schedule, &uid, cal_req);
+                // create_ical_event() added the all-day alarm if requested
+                if cal_req.alarm_allday.is_none() {
+                    let summary = get_summary(&last_day_schedule, language);
+                    add_alarm(
+                        &mut ical_event,
+                        default_ending_alarm(),
+                        AlarmRelated::Start,
+                        &summary,
+                    );
+                }
+                events.push(ical_event);
+            }
         } else {
-            events.push(create_ical_event(&schedule, language));
+            events.push(create_ical_event(&schedule, &schedule_uid(&schedule), cal_req));
+        }
+    }
+    events
+}
+
+fn generate_events_lastchance(
+    schedules: Vec<Schedule>,
+    cal_req: &CalendarRequest,
+) -> Vec<CalendarEvent> {
+    let (language, tz) = (cal_req.language, cal_req.tz);
+    let prefix = match cal_req.lastchance_days {
+        7 => language.tr("lastchance-week").to_string(),
+        days => language.tr_count("lastchance-days", days as usize),
+    };
+    let mut events: Vec<CalendarEvent> = Vec::new();
+    for schedule in schedules {
+        if !schedule.is_long_term {
+            events.push(create_ical_event(&schedule, &schedule_uid(&schedule), cal_req));
+            continue;
         }
+
+        // end date is exclusive, so this is the first of the last `lastchance_days` days
+        let last_days_start =
+            schedule.end_date(tz) - Duration::days(cal_req.lastchance_days.into());
+        let last_date = schedule.last_date(tz);
+        let reminder_date = last_days_start.max(schedule.start_date(tz));
+        let mut reminder_schedule = schedule.clone();
+        Rc::make_mut(&mut reminder_schedule.event).name =
+            format!("{}{}", prefix, schedule.event.name);
+        reminder_schedule.start = start_of_day(reminder_date, tz);
+        reminder_schedule.end = start_of_day(reminder_date + Duration::days(1), tz);
+        let uid = format!("lastchance/Schedule#{}@goout.net", schedule.id);
+        let mut ical_event = create_ical_event(&reminder_schedule, &uid, cal_req);
+        ical_event.description(&format!(
+            "{} - {}\n\n{}",
+            schedule.start_date(tz),
+            last_date,
+            event_description(&schedule, cal_req)
+        ));
+        events.push(ical_event);
     }
     events
 }
@@ -105,35 +425,107 @@ struct BreakDay<'a> {
 
 type BreakDayMap<'a> = BTreeMap<NaiveDate, BreakDay<'a>>;
 
-fn generate_events_aggregate(schedules: Vec<Schedule>, lang: &str) -> Vec<IcalEvent> {
+fn generate_events_aggregate(
+    schedules: Vec<Schedule>,
+    cal_req: &CalendarRequest,
+) -> Vec<CalendarEvent> {
     // TODO: possibly made more incremental by using forceSortByStart=ASC and tweaking algorithm.
-    let mut events: Vec<IcalEvent> = Vec::new();
-    let mut breakdays: BreakDayMap = BTreeMap::new();
+    let mut events: Vec<CalendarEvent> = Vec::new();
+    let breakdays = collect_breakdays(&schedules, &mut events, cal_req);
+    render_events_from_breakdays(&mut events, &breakdays, cal_req);
+    events
+}
+
+fn generate_events_weekly(
+    schedules: Vec<Schedule>,
+    cal_req: &CalendarRequest,
+) -> Vec<CalendarEvent> {
+    let mut events: Vec<CalendarEvent> = Vec::new();
+    let breakdays = collect_breakdays(&schedules, &mut events, cal_req);
+
+    // GoOut expects `after` as date, don't fail if some other value gets through to it
+    let first_day = cal_req
+        .after
+        .as_ref()
+        .and_then(|after| NaiveDate::parse_from_str(after, "%Y-%m-%d").ok())
+        .unwrap_or_else(|| Utc::now().with_timezone(&cal_req.tz).date().naive_local());
+    let first_monday =
+        first_day - Duration::days(first_day.weekday().num_days_from_monday().into());
+
+    let feed = feed_id(cal_req);
+    let mut breakday_iter = breakdays.iter().peekable();
+    let mut active_schedules: Vec<&Schedule> = Vec::new();
+    for week in 0..WEEKLY_HORIZON_WEEKS {
+        let week_start = first_monday + Duration::weeks(week);
+        let week_end = week_start + Duration::weeks(1);
+        while let Some((_, breakday)) = breakday_iter.next_if(|(&date, _)| date <= week_start) {
+            active_schedules.retain(|s| !breakday.end_ids.contains(&s.id));
+            active_schedules.extend_from_slice(&breakday.starts);
+        }
+
+        let mut week_schedules = active_schedules.clone();
+        for breakday in breakdays.range(week_start.succ()..week_end).map(|(_, b)| b) {
+            week_schedules.extend_from_slice(&breakday.starts);
+        }
+        if week_schedules.is_empty() {
+            continue;
+        }
+        let uid = format!("weekly/User#{}/{}/{}@goout.net", cal_req.id, feed, week_start);
+        let mut ical_event =
+            render_aggregate_event(&uid, week_start, week_end, &week_schedules, cal_req);
+        // the entry is on Monday only, not to clutter the whole week
+        ical_event.end_date(to_ical_date(week_start.succ()));
+        set_revision(&mut ical_event, &uid, &week_schedules, cal_req);
+        events.push(ical_event);
+    }
+    events
+}
 
+/// Collect start and end days of long-term `schedules`, push events of other schedules to `events`.
+fn collect_breakdays<'a>(
+    schedules: &'a [Schedule],
+    events: &mut Vec<CalendarEvent>,
+    cal_req: &CalendarRequest,
+) -> BreakDayMap<'a> {
+    let mut breakdays: BreakDayMap = BTreeMap::new();
     for schedule in schedules.iter() {
         if !schedule.is_long_term {
-            events.push(create_ical_event(schedule, lang));
+            events.push(create_ical_event(schedule, &schedule_uid(schedule), cal_req));
             continue;
         }
 
-        let start_breakday = breakdays.entry(schedule.start_date()).or_default();
+        let start_breakday = breakdays.entry(schedule.start_date(cal_req.tz)).or_default();
         start_breakday.starts.push(schedule);
 
-        let end_breakday = breakdays.entry(schedule.end_date()).or_default();
+        let end_breakday = breakdays.entry(schedule.end_date(cal_req.tz)).or_default();
         end_breakday.end_ids.insert(schedule.id);
     }
-
-    render_events_from_breakdays(&mut events, &breakdays, lang);
-    events
+    breakdays
 }
 
-fn render_events_from_breakdays(events: &mut Vec<IcalEvent>, breakdays: &BreakDayMap, lang: &str) {
+fn render_events_from_breakdays(
+    events: &mut Vec<CalendarEvent>,
+    breakdays: &BreakDayMap,
+    cal_req: &CalendarRequest,
+) {
+    let feed = feed_id(cal_req);
     let mut date_cursor: NaiveDate = MIN_DATE;
     let mut active_schedules: Vec<&Schedule> = Vec::new();
 
     for (&date, breakday) in breakdays.iter() {
         if !active_schedules.is_empty() {
-            events.push(render_aggregate_event(date_cursor, date, &active_schedules, lang));
+            for (start, end) in allowed_periods(date_cursor, date, cal_req) {
+                let uid = match cal_req.uids {
+                    UidScheme::Legacy => format!("LongTermSchedule{}@goout.net", start),
+                    UidScheme::V2 => {
+                        format!("aggregate/User#{}/{}/{}@goout.net", cal_req.id, feed, start)
+                    }
+                };
+                let mut event =
+                    render_aggregate_event(&uid, start, end, &active_schedules, cal_req);
+                set_revision(&mut event, &uid, &active_schedules, cal_req);
+                events.push(event);
+            }
         }
         active_schedules.retain(|s| !breakday.end_ids.contains(&s.id));
         active_schedules.extend_from_slice(&breakday.starts);
@@ -142,87 +534,392 @@ fn render_events_from_breakdays(events: &mut Vec<IcalEvent>, breakdays: &BreakDa
     assert_eq!(active_schedules.len(), 0, "Active schedules not exhausted.")
 }
 
+/// Split period from `start` to `end` (exclusive) into maximal periods of days allowed by the days of
+/// week filter.
+fn allowed_periods(
+    start: NaiveDate,
+    end: NaiveDate,
+    cal_req: &CalendarRequest,
+) -> Vec<(NaiveDate, NaiveDate)> {
+    let filters = &cal_req.filters;
+    if filters.days.is_empty() {
+        return vec![(start, end)];
+    }
+    let mut periods = Vec::new();
+    let mut period_start = None;
+    let mut date = start;
+    while date < end {
+        match (period_start, filters.allows_day(date)) {
+            (None, true) => period_start = Some(date),
+            (Some(allowed_start), false) => {
+                periods.push((allowed_start, date));
+                period_start = None;
+            }
+            _ => {}
+        }
+        date = date.succ();
+    }
+    if let Some(allowed_start) = period_start {
+        periods.push((allowed_start, end));
+    }
+    periods
+}
+
+/// Render all-day event with `uid` from `start` to `end` (exclusive) that summarises long-term
+/// `schedules` active in the period by their phase. Revision is left to the caller, which may
+/// still adjust the event.
 fn render_aggregate_event(
+    uid: &str,
     start: NaiveDate,
     end: NaiveDate,
     schedules: &Vec<&Schedule>,
-    lang: &str,
-) -> IcalEvent {
+    cal_req: &CalendarRequest,
+) -> CalendarEvent {
+    use EventPhase::*;
     assert_ne!(schedules.len(), 0, "render_aggregate_event() must have at least 1 schedule.");
-    let mut ical_event = IcalEvent::new();
+    let (lang, tz) = (cal_req.language, cal_req.tz);
+    let mut ical_event = CalendarEvent::new();
 
-    ical_event.uid(&format!("LongTermSchedule{}@goout.net", start));
+    ical_event.uid(uid);
+    ical_event.start_date(to_ical_date(start));
+    ical_event.end_date(to_ical_date(end));
 
-    // icalendar currently needs Date (with timezone), but doesn't actually use the TZ. Convert.
-    ical_event.start_date(Utc.from_utc_date(&start));
-    ical_event.end_date(Utc.from_utc_date(&end));
+    let mut categorised: BTreeMap<EventPhase, Vec<&Schedule>> = BTreeMap::new();
+    for s in schedules.iter() {
+        categorised.entry(EventPhase::of(s, start, end, tz)).or_default().push(s);
+    }
 
-    if schedules.len() == 1 {
+    let summary = if schedules.len() == 1 {
         let schedule = schedules[0];
-        fill_basic_ical_event_props(&mut ical_event, schedule, lang);
+        fill_basic_ical_event_props(&mut ical_event, schedule, lang, &cal_req.colors);
 
         ical_event.description(&format!(
             "{} - {}\n\n{}",
-            schedule.start_date(),
-            schedule.end_date(),
-            get_description(schedule, OptionalDescFields::default())
+            schedule.start_date(tz),
+            schedule.last_date(tz),
+            event_description(schedule, cal_req)
         ));
-        return ical_event;
+        get_summary(schedule, lang)
+    } else {
+        set_categories(&mut ical_event, schedules.iter().flat_map(|s| s.event.categories.values()));
+
+        // Permanent schedules continue almost every day, counting them would be just noise.
+        let counted = |phase: EventPhase, s: &&Schedule| phase != Continued || !s.permanent;
+        let only_permanent = categorised.iter().all(|(&p, ss)| !ss.iter().any(|s| counted(p, s)));
+        let mut counts: BTreeMap<EventPhase, usize> = BTreeMap::new();
+        for (&phase, phase_schedules) in categorised.iter() {
+            let count =
+                phase_schedules.iter().filter(|s| only_permanent || counted(phase, s)).count();
+            if count == 0 {
+                continue;
+            }
+            let count_phases = if phase == BeginEnd { vec![Begin, End] } else { vec![phase] };
+            for count_phase in count_phases {
+                *counts.entry(count_phase).or_default() += count;
+            }
+        }
+
+        let summary = counts
+            .into_iter()
+            .map(|(phase, len)| phase.format_count(len, lang))
+            .collect::<Vec<_>>()
+            .join(", ");
+        ical_event.summary(&summary);
+        ical_event.description(
+            &categorised
+                .iter()
+                .flat_map(|(phase, schedules)| {
+                    schedules.iter().map(move |s| get_longterm_part_description(*phase, s, cal_req))
+                })
+                .collect::<Vec<_>>()
+                .join("\n\n"),
+        );
+        summary
+    };
+
+    // Remind of events that begin or end within this entry, but not of those that just continue.
+    // Ending ones are reminded of even without the all-day alarm.
+    if let Some(trigger) = cal_req.alarm_allday {
+        if categorised.keys().any(|&phase| phase == Begin || phase == BeginEnd) {
+            add_alarm(&mut ical_event, trigger, AlarmRelated::Start, &summary);
+        }
+    }
+    if categorised.keys().any(|&phase| phase == End || phase == BeginEnd) {
+        let trigger = cal_req.alarm_allday.unwrap_or_else(default_ending_alarm);
+        add_alarm(&mut ical_event, trigger, AlarmRelated::LastDay, &summary);
     }
 
-    set_dtstamp(&mut ical_event, schedules[0]);
+    ical_event
+}
 
-    let mut categorised: BTreeMap<EventPhase, Vec<&Schedule>> = BTreeMap::new();
-    let mut counts: BTreeMap<EventPhase, usize> = BTreeMap::new();
-    for s in schedules.iter() {
-        use EventPhase::*;
-        let phase = match (s.start_date() == start, s.end_date() == end) {
-            (true, true) => BeginEnd,
-            (true, false) => Begin,
-            (false, true) => End,
-            (false, false) => Continued,
-        };
-        categorised.entry(phase).or_default().push(s);
+/// Split timed schedules spanning more days into one event per day, each from the start time to the
+/// end time of the schedule (ending the next day if that is earlier). Only done in the multiday
+/// split mode and for schedules with made-up time, for which a block over many days would be worse
+/// than the all-day event they replace. Returns the per-day events and the other schedules.
+fn generate_events_multiday(
+    schedules: Vec<Schedule>,
+    cal_req: &CalendarRequest,
+) -> (Vec<CalendarEvent>, Vec<Schedule>) {
+    let (tz, filters) = (cal_req.tz, &cal_req.filters);
+    let mut events = Vec::new();
+    let mut rest = Vec::new();
+    for schedule in schedules {
+        if !is_split_per_day(&schedule, cal_req) {
+            rest.push(schedule);
+            continue;
+        }
+        let (start_time, end_time, last_start_date) = daily_times(&schedule, tz);
+        let overnight = end_time <= start_time;
 
-        let count_phases = if phase == BeginEnd { vec![Begin, End] } else { vec![phase] };
-        for count_phase in count_phases {
-            *counts.entry(count_phase).or_default() += 1;
+        let mut date = schedule.start_date(tz);
+        while date <= last_start_date {
+            let mut day_schedule = schedule.clone();
+            day_schedule.start = local_datetime(date, start_time, tz);
+            let end_date = if overnight { date + Duration::days(1) } else { date };
+            day_schedule.end = local_datetime(end_date, end_time, tz);
+            // made-up times are not filtered, like those of the all-day schedules they replace
+            let passes_filters = if schedule.approximate_time {
+                filters.allows_day(date)
+            } else {
+                let local_tz = schedule.local_tz(tz);
+                passes_timed_filters(day_schedule.start, day_schedule.end, local_tz, filters)
+            };
+            if passes_filters {
+                let uid = format!("multiday/Schedule#{}/{}@goout.net", schedule.id, date);
+                events.push(create_ical_event(&day_schedule, &uid, cal_req));
+            }
+            date += Duration::days(1);
         }
     }
+    (events, rest)
+}
 
-    ical_event.summary(
-        &counts
-            .into_iter()
-            .map(|(phase, len)| phase.format_count(len, lang))
-            .collect::<Vec<_>>()
-            .join(", "),
-    );
-    ical_event.description(
-        &categorised
-            .iter()
-            .flat_map(|(phase, schedules)| {
-                schedules.iter().map(move |s| get_longterm_part_description(*phase, s, lang))
-            })
-            .collect::<Vec<_>>()
-            .join("\n\n"),
-    );
+/// Whether generate_events_multiday() splits timed `schedule` into one event per day.
+pub(super) fn is_split_per_day(schedule: &Schedule, cal_req: &CalendarRequest) -> bool {
+    let split_all = matches!(cal_req.multiday, MultidayHandling::Split);
+    if (!split_all && !schedule.approximate_time) || is_all_day(schedule) {
+        return false;
+    }
+    let (_, _, last_start_date) = daily_times(schedule, cal_req.tz);
+    last_start_date > schedule.start_date(cal_req.tz)
+}
 
+/// Start and end time of day of `schedule` in `tz` and the last date an occurrence of it starts on
+/// when split into days.
+fn daily_times(schedule: &Schedule, tz: Tz) -> (NaiveTime, NaiveTime, NaiveDate) {
+    let start_time = schedule.start.with_timezone(&tz).time();
+    let end_time = schedule.end.with_timezone(&tz).time();
+    let last_start_date = if end_time <= start_time {
+        schedule.end_date(tz) - Duration::days(1)
+    } else {
+        schedule.end_date(tz)
+    };
+    (start_time, end_time, last_start_date)
+}
+
+/// Merge timed schedules of the same event at the same venue on the same day into one event
+/// spanning from the first start to the last end. Returns the merged events and the other schedules.
+fn generate_events_sameday(
+    schedules: Vec<Schedule>,
+    cal_req: &CalendarRequest,
+) -> (Vec<CalendarEvent>, Vec<Schedule>) {
+    let tz = cal_req.tz;
+    let mut by_event_day: BTreeMap<(u64, NaiveDate), Vec<&Schedule>> = BTreeMap::new();
+    for schedule in schedules.iter().filter(|s| !is_all_day(s)) {
+        let key = (schedule.event_id, schedule.start_date(tz));
+        by_event_day.entry(key).or_default().push(schedule);
+    }
+
+    let mut events: Vec<CalendarEvent> = Vec::new();
+    let mut merged_ids: HashSet<u64> = HashSet::new();
+    for ((event_id, date), mut group) in by_event_day {
+        let first = group[0];
+        if group.len() < 2 || !group.iter().all(|s| s.venue_id == first.venue_id) {
+            continue;
+        }
+        group.sort_by_key(|s| s.start);
+        let uid = format!("sameday/Event#{}/{}@goout.net", event_id, date);
+        events.push(render_sameday_event(&group, &uid, cal_req));
+        merged_ids.extend(group.iter().map(|s| s.id));
+    }
+    // keep the original order of the rest
+    let rest = schedules.into_iter().filter(|s| !merged_ids.contains(&s.id)).collect();
+    (events, rest)
+}
+
+/// Render same-day `schedules` (sorted by start) as one event, whose description lists the
+/// individual slots with their own URLs and pricing.
+fn render_sameday_event(
+    schedules: &[&Schedule],
+    uid: &str,
+    cal_req: &CalendarRequest,
+) -> CalendarEvent {
+    let first = schedules[0];
+    let mut merged = first.clone();
+    merged.end = schedules.iter().map(|s| s.end).max().expect("Empty schedules.");
+    merged.cancelled = schedules.iter().all(|s| s.cancelled);
+    let mut ical_event = create_ical_event_of(&merged, uid, schedules, cal_req);
+
+    let mut description = schedules
+        .iter()
+        .map(|s| get_sameday_slot_description(s, cal_req))
+        .collect::<Vec<_>>()
+        .join("\n\n");
+    let text = first.event.text.trim();
+    if !text.is_empty() {
+        write!(description, "\n\n{}", text).expect("Writing to String should not fail.");
+    }
+    // all slots are at the same venue
+    description.push_str(&distance_suffix(first, cal_req));
+    ical_event.description(&description);
     ical_event
 }
 
-fn get_longterm_part_description(phase: EventPhase, schedule: &Schedule, lang: &str) -> String {
+fn get_sameday_slot_description(schedule: &Schedule, cal_req: &CalendarRequest) -> String {
+    let cancelled_prefix = if schedule.cancelled { cal_req.language.tr("cancelled") } else { "" };
     format!(
-        "{}{}\n{} - {}\n{}",
-        phase.prefix(lang),
-        get_summary(schedule, lang),
-        schedule.start_date(),
-        schedule.end_date(),
+        "{}{}–{}\n{}",
+        cancelled_prefix,
+        schedule.start.with_timezone(&cal_req.tz).format("%H:%M"),
+        schedule.end.with_timezone(&cal_req.tz).format("%H:%M"),
         get_description(schedule, OptionalDescFields::empty())
     )
 }
 
-fn fill_basic_ical_event_props(ical_event: &mut IcalEvent, schedule: &Schedule, language: &str) {
-    set_dtstamp(ical_event, schedule);
+/// Collapse repeated (not long-term) schedules of the same event into recurring events. Returns
+/// the recurring events and the schedules that cannot be collapsed.
+fn generate_events_recurring(
+    schedules: Vec<Schedule>,
+    cal_req: &CalendarRequest,
+) -> (Vec<CalendarEvent>, Vec<Schedule>) {
+    let mut by_event: BTreeMap<u64, Vec<&Schedule>> = BTreeMap::new();
+    for schedule in schedules.iter().filter(|s| !s.is_long_term) {
+        by_event.entry(schedule.event_id).or_default().push(schedule);
+    }
+
+    let mut events: Vec<CalendarEvent> = Vec::new();
+    let mut collapsed_ids: HashSet<u64> = HashSet::new();
+    for (event_id, mut group) in by_event {
+        group.sort_by_key(|s| s.start);
+        let first = group[0];
+        // A single VEVENT can only have one location and duration.
+        let collapsible = group.len() > 1
+            && group.iter().any(|s| !s.cancelled)
+            && group.iter().all(|s| {
+                s.venue_id == first.venue_id
+                    && s.end - s.start == first.end - first.start
+                    && s.hour_ignored == first.hour_ignored
+            });
+        if collapsible {
+            events.push(render_recurring_event(&group, cal_req));
+            collapsed_ids.insert(event_id);
+        }
+    }
+    // keep the original order of the rest
+    let rest = schedules
+        .into_iter()
+        .filter(|s| s.is_long_term || !collapsed_ids.contains(&s.event_id))
+        .collect();
+    (events, rest)
+}
+
+/// Render schedules of one event (sorted by start) as a single VEVENT with an RRULE when they
+/// repeat regularly, or with RDATEs otherwise. Cancelled schedules become EXDATEs.
+fn render_recurring_event(schedules: &[&Schedule], cal_req: &CalendarRequest) -> CalendarEvent {
+    let first = schedules[0];
+    // cancellation of the first instance is expressed by EXDATE
+    let mut master = first.clone();
+    master.cancelled = false;
+    let uid = format!("recurring/Event#{}@goout.net", first.event_id);
+    let mut ical_event = create_ical_event_of(&master, &uid, schedules, cal_req);
+
+    let format_start = |schedule: &Schedule| {
+        if is_all_day(schedule) {
+            schedule.start_date(cal_req.tz).format("%Y%m%d").to_string()
+        } else {
+            schedule.start.with_timezone(&Utc).format("%Y%m%dT%H%M%SZ").to_string()
+        }
+    };
+    let add_dates_property = |ical_event: &mut IcalEvent, name: &str, values: Vec<String>| {
+        if values.is_empty() {
+            return;
+        }
+        let mut property = Property::new(name, &values.join(","));
+        if is_all_day(first) {
+            property.add_parameter("VALUE", "DATE");
+        }
+        ical_event.append_property(property);
+    };
+
+    match recurrence_rule(schedules, cal_req.tz) {
+        Some(rrule) => {
+            ical_event.add_property("RRULE", &rrule);
+        }
+        None => {
+            let rdates = schedules[1..].iter().map(|s| format_start(s)).collect();
+            add_dates_property(&mut ical_event, "RDATE", rdates);
+        }
+    }
+    let exdates = schedules.iter().filter(|s| s.cancelled).map(|s| format_start(s)).collect();
+    add_dates_property(&mut ical_event, "EXDATE", exdates);
+    ical_event
+}
+
+/// RRULE of `schedules` (sorted by start) if at least 3 of them repeat in a regular interval of
+/// whole days. Timed events are in UTC, so a series across DST change is not regular.
+fn recurrence_rule(schedules: &[&Schedule], tz: Tz) -> Option<String> {
+    if schedules.len() < 3 {
+        return None;
+    }
+    let interval = if is_all_day(schedules[0]) {
+        schedules[1].start_date(tz) - schedules[0].start_date(tz)
+    } else {
+        schedules[1].start - schedules[0].start
+    };
+    let regular = schedules.windows(2).all(|pair| {
+        let pair_interval = if is_all_day(pair[0]) {
+            pair[1].start_date(tz) - pair[0].start_date(tz)
+        } else {
+            pair[1].start - pair[0].start
+        };
+        pair_interval == interval
+    });
+    if !regular || interval.num_days() < 1 || interval != Duration::days(interval.num_days()) {
+        return None;
+    }
+
+    let days = interval.num_days();
+    let (freq, count) = if days % 7 == 0 { ("WEEKLY", days / 7) } else { ("DAILY", days) };
+    let mut rrule = format!("FREQ={}", freq);
+    if count > 1 {
+        write!(rrule, ";INTERVAL={}", count).expect("Writing to String should not fail.");
+    }
+    write!(rrule, ";COUNT={}", schedules.len()).expect("Writing to String should not fail.");
+    Some(rrule)
+}
+
+fn get_longterm_part_description(
+    phase: EventPhase,
+    schedule: &Schedule,
+    cal_req: &CalendarRequest,
+) -> String {
+    format!(
+        "{}{}\n{} - {}\n{}{}",
+        phase.prefix(cal_req.language),
+        get_summary(schedule, cal_req.language),
+        schedule.start_date(cal_req.tz),
+        schedule.last_date(cal_req.tz),
+        get_description(schedule, OptionalDescFields::empty()),
+        distance_suffix(schedule, cal_req)
+    )
+}
+
+fn fill_basic_ical_event_props(
+    ical_event: &mut IcalEvent,
+    schedule: &Schedule,
+    language: Language,
+    colors: &CategoryColors,
+) {
     ical_event.add_property("URL", &schedule.url);
     set_cancelled(ical_event, schedule.cancelled);
 
@@ -234,48 +931,263 @@ fn fill_basic_ical_event_props(ical_event: &mut IcalEvent, schedule: &Schedule,
     ical_event.add_property("GEO", &format!("{};{}", venue.latitude, venue.longitude));
 
     ical_event.summary(&get_summary(schedule, language));
+
+    let categories = schedule.event.categories.values();
+    set_categories(ical_event, categories.clone());
+    if let Some(color) = categories.filter_map(|c| colors.0.get(&c.kind.to_lowercase())).next() {
+        ical_event.add_property("COLOR", color);
+    }
+    if let Some(image) = &schedule.event.main_image {
+        ical_event.append_property(
+            Property::new("IMAGE", &image.url()).add_parameter("VALUE", "URI").done(),
+        );
+    }
 }
 
-fn create_ical_event(schedule: &Schedule, language: &str) -> IcalEvent {
-    let mut ical_event = IcalEvent::new();
-    fill_basic_ical_event_props(&mut ical_event, schedule, language);
+/// UID of an event that directly represents a GoOut schedule. Same in all feeds and UID schemes, as
+/// the event is the same for all users.
+fn schedule_uid(schedule: &Schedule) -> String {
+    // permanent schedule shrunk to a single day by permanent_once() is a different event
+    if schedule.permanent && !schedule.is_long_term {
+        return format!("permanent/Schedule#{}@goout.net", schedule.id);
+    }
+    format!("Schedule#{}@goout.net", schedule.id)
+}
 
-    ical_event.uid(&format!("Schedule#{}@goout.net", schedule.id));
-    set_start_end(&mut ical_event, schedule);
-    ical_event.description(&get_description(schedule, OptionalDescFields::default()));
+/// UID of an event representing `day` of a long-term `schedule` in the split mode.
+fn split_uid(schedule: &Schedule, day: SplitDay, uids: UidScheme) -> String {
+    match uids {
+        UidScheme::Legacy => {
+            let offset = match day {
+                SplitDay::First => 1_000_000_000_000,
+                SplitDay::Last => 2_000_000_000_000,
+            };
+            format!("Schedule#{}@goout.net", offset + schedule.id)
+        }
+        UidScheme::V2 => format!("split/Schedule#{}/{}@goout.net", schedule.id, day.uid_part()),
+    }
+}
+
+/// Identity of the feed given by parameters of `cal_req` that affect its events, so that the
+/// summarising events of feeds of one user with e.g. different filters or timezone don't share
+/// UIDs, and that revisions of events in such feeds are tracked separately.
+fn feed_id(cal_req: &CalendarRequest) -> String {
+    // Hash a string, like set_revision(), so that the identity is stable between builds.
+    let mut hasher = FnvHasher::default();
+    hasher.write(
+        format!(
+            "{:?}|{:?}|{:?}|{}|{:?}|{}|{:?}|{:?}|{:?}|{:?}|{}|{}|{:?}",
+            cal_req.language,
+            cal_req.longterm,
+            cal_req.longterm_min_days,
+            cal_req.lastchance_days,
+            cal_req.permanent,
+            cal_req.tz.name(),
+            cal_req.repeats,
+            cal_req.samedays,
+            cal_req.multiday,
+            cal_req.allday,
+            cal_req.allday_time,
+            cal_req.allday_duration,
+            cal_req.filters
+        )
+        .as_bytes(),
+    );
+    format!("{:016x}", hasher.finish())
+}
+
+fn create_ical_event(schedule: &Schedule, uid: &str, cal_req: &CalendarRequest) -> CalendarEvent {
+    create_ical_event_of(schedule, uid, &[schedule], cal_req)
+}
+
+/// Create event from `schedule` whose revision is given by `revision_schedules`.
+fn create_ical_event_of(
+    schedule: &Schedule,
+    uid: &str,
+    revision_schedules: &[&Schedule],
+    cal_req: &CalendarRequest,
+) -> CalendarEvent {
+    let mut ical_event = CalendarEvent::new();
+    fill_basic_ical_event_props(&mut ical_event, schedule, cal_req.language, &cal_req.colors);
+
+    ical_event.uid(uid);
+    set_start_end(&mut ical_event, schedule, cal_req.tz);
+    set_revision(&mut ical_event, uid, revision_schedules, cal_req);
+    ical_event.description(&event_description(schedule, cal_req));
+    let transparent = matches!(cal_req.allday, AlldayHandling::Transparent);
+    if transparent && schedule.hour_ignored && !schedule.is_long_term {
+        ical_event.add_property("TRANSP", "TRANSPARENT");
+    }
+
+    let trigger = if is_all_day(schedule) { cal_req.alarm_allday } else { cal_req.alarm };
+    if let Some(trigger) = trigger {
+        let summary = get_summary(schedule, cal_req.language);
+        add_alarm(&mut ical_event, trigger, AlarmRelated::Start, &summary);
+    }
 
     ical_event
 }
 
-fn set_dtstamp(ical_event: &mut IcalEvent, schedule: &Schedule) {
-    let uploaded_on_str = &schedule.uploaded_on.format("%Y%m%dT%H%M%S").to_string();
+/// Description of an event of a single `schedule`, with a note about its approximate time and
+/// distances from homes where applicable.
+fn event_description(schedule: &Schedule, cal_req: &CalendarRequest) -> String {
+    let mut description = get_description(schedule, OptionalDescFields::default());
+    if schedule.approximate_time {
+        description = format!("{}\n{}", cal_req.language.tr("approximate-time"), description);
+    }
+    description + &distance_suffix(schedule, cal_req)
+}
+
+/// Line with distances of the venue of `schedule` from homes, preceded by a newline. Empty if no
+/// homes are given.
+fn distance_suffix(schedule: &Schedule, cal_req: &CalendarRequest) -> String {
+    if cal_req.homes.is_empty() {
+        return String::new();
+    }
+    let venue_coordinates = schedule.venue.coordinates();
+    let distances = cal_req
+        .homes
+        .iter()
+        .map(|h| format!("{} {:.0} km", h.name, distance_km(h.coordinates, venue_coordinates)))
+        .collect::<Vec<_>>()
+        .join(", ");
+    format!("\n{}{}", cal_req.language.tr("distance"), distances)
+}
+
+/// Set DTSTAMP, LAST-MODIFIED and SEQUENCE of an event with `uid` composed of `schedules`. Must be
+/// called after DTSTART, DTEND and SUMMARY of the event are set.
+fn set_revision(
+    ical_event: &mut IcalEvent,
+    uid: &str,
+    schedules: &[&Schedule],
+    cal_req: &CalendarRequest,
+) {
+    let uploaded_on = schedules.iter().map(|s| s.uploaded_on).max().expect("Empty schedules.");
+    let uploaded_on_str = &uploaded_on.with_timezone(&Utc).format("%Y%m%dT%H%M%SZ").to_string();
+    // We serve a published calendar (without METHOD), where DTSTAMP means last revision time.
     ical_event.add_property("DTSTAMP", uploaded_on_str);
+    ical_event.add_property("LAST-MODIFIED", uploaded_on_str);
+
+    // Only changes interesting for attendees are reflected. Fingerprint needs to be stable between
+    // builds, that's why we hash a string and not the values directly.
+    let mut hasher = FnvHasher::default();
+    for schedule in schedules {
+        let venue = &schedule.venue;
+        hasher.write(
+            format!(
+                "{}|{}|{}|{}|{}|{}\n",
+                schedule.id,
+                schedule.start.to_rfc3339(),
+                schedule.end.to_rfc3339(),
+                venue.name,
+                venue.address,
+                schedule.cancelled
+            )
+            .as_bytes(),
+        );
+    }
+    // Aggregate events are keyed by their start only, their end and summary change with the set of
+    // schedules shown in them.
+    for key in &["DTSTART", "DTEND", "SUMMARY"] {
+        if let Some(property) = ical_event.properties().get(*key) {
+            let mut line = String::new();
+            property.fmt_write(&mut line).expect("Writing to String should not fail.");
+            hasher.write(line.as_bytes());
+        }
+    }
+    // UIDs of some events are not unique across users and feeds, prefix them with both.
+    let key = format!("{}/{}/{}", cal_req.id, feed_id(cal_req), uid);
+    let sequence = sequence::get(&key, hasher.finish());
+    ical_event.add_property("SEQUENCE", &sequence.to_string());
+}
+
+pub(super) const fn is_all_day(schedule: &Schedule) -> bool {
+    schedule.hour_ignored || schedule.is_long_term
 }
 
-fn set_start_end(ical_event: &mut IcalEvent, schedule: &Schedule) {
-    if schedule.hour_ignored || schedule.is_long_term {
-        ical_event.start_date(schedule.start.date());
-        ical_event.end_date(schedule.end.date());
+fn set_start_end(ical_event: &mut IcalEvent, schedule: &Schedule, tz: Tz) {
+    if is_all_day(schedule) {
+        ical_event.start_date(to_ical_date(schedule.start_date(tz)));
+        ical_event.end_date(to_ical_date(schedule.end_date(tz)));
     } else {
         ical_event.starts(schedule.start.with_timezone(&Utc));
         ical_event.ends(schedule.end.with_timezone(&Utc));
     }
 }
 
+fn add_alarm(
+    ical_event: &mut CalendarEvent,
+    trigger: AlarmTrigger,
+    related: AlarmRelated,
+    description: &str,
+) {
+    let trigger = match related {
+        AlarmRelated::Start => Property::new("TRIGGER", &format_ical_duration(trigger.0)),
+        AlarmRelated::LastDay => {
+            // DTEND of all-day events is exclusive, shift the trigger to the start of the last day
+            let last_day_trigger = trigger.0 - Duration::days(1);
+            Property::new("TRIGGER", &format_ical_duration(last_day_trigger))
+                .add_parameter("RELATED", "END")
+                .done()
+        }
+    };
+
+    ical_event.alarms.push(Alarm { trigger, description: description.to_string() });
+}
+
+/// Alarm on the last day of long-term events in the split and aggregate modes if no all-day alarm is
+/// requested: at 9:00 the day before, so that closing exhibitions aren't missed.
+fn default_ending_alarm() -> AlarmTrigger {
+    AlarmTrigger(Duration::hours(-15))
+}
+
+/// Format duration in RFC 5545 format (section 3.3.6), e.g. "-PT2H" or "P1DT12H".
+fn format_ical_duration(duration: Duration) -> String {
+    let sign = if duration < Duration::zero() { "-" } else { "" };
+    let total_seconds = duration.num_seconds().abs();
+    let days = total_seconds / 86_400;
+    let (hours, minutes, seconds) =
+        (total_seconds % 86_400 / 3_600, total_seconds % 3_600 / 60, total_seconds % 60);
+
+    let mut formatted = format!("{}P", sign);
+    if days > 0 {
+        formatted.push_str(&format!("{}D", days));
+    }
+    if hours > 0 || minutes > 0 || seconds > 0 || days == 0 {
+        formatted.push('T');
+        for (value, designator) in &[(hours, 'H'), (minutes, 'M'), (seconds, 'S')] {
+            if *value > 0 {
+                formatted.push_str(&format!("{}{}", value, designator));
+            }
+        }
+        if total_seconds == 0 {
+            formatted.push_str("0S");
+        }
+    }
+    formatted
+}
+
+/// Set CATEGORIES from names of given `categories`, skipping duplicates.
+fn set_categories<'a>(ical_event: &mut IcalEvent, categories: impl Iterator<Item = &'a Category>) {
+    let mut names: Vec<String> = Vec::new();
+    for category in categories {
+        // comma separates categories, escape it (and other special characters) in the names
+        let name = category.name.replace('\\', "\\\\").replace(',', "\\,").replace(';', "\\;");
+        if !names.contains(&name) {
+            names.push(name);
+        }
+    }
+    if !names.is_empty() {
+        ical_event.add_property("CATEGORIES", &names.join(","));
+    }
+}
+
 fn set_cancelled(ical_event: &mut IcalEvent, cancelled: bool) {
     ical_event.add_property("STATUS", if cancelled { "CANCELLED" } else { "CONFIRMED" });
 }
 
-fn get_summary(schedule: &Schedule, language: &str) -> String {
-    let cancelled_prefix = if schedule.cancelled {
-        // TODO: poor man's localisation
-        match language {
-            "cs" => "Zrušeno: ",
-            _ => "Cancelled: ",
-        }
-    } else {
-        ""
-    };
+pub(super) fn get_summary(schedule: &Schedule, language: Language) -> String {
+    let cancelled_prefix = if schedule.cancelled { language.tr("cancelled") } else { "" };
 
     format!(
         "{}{} ({})",
@@ -292,7 +1204,7 @@ fn get_summary(schedule: &Schedule, language: &str) -> String {
 }
 
 bitflags! {
-    struct OptionalDescFields: u32 {
+    pub(super) struct OptionalDescFields: u32 {
         const EVENT_TEXT = 0b00000001;
     }
 }
@@ -303,7 +1215,7 @@ impl Default for OptionalDescFields {
     }
 }
 
-fn get_description(schedule: &Schedule, optional_fields: OptionalDescFields) -> String {
+pub(super) fn get_description(schedule: &Schedule, optional_fields: OptionalDescFields) -> String {
     let mut description = Vec::<&str>::new();
 
     let performer_names = schedule
@@ -338,3 +1250,418 @@ fn get_description(schedule: &Schedule, optional_fields: OptionalDescFields) ->
 
     description.into_iter().filter(|e| !e.trim().is_empty()).collect::<Vec<_>>().join("\n")
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::calendar::{Coordinates, HomeLocation, OutputFormat, ScheduleFilters};
+    use crate::generation::{Event, Locality, NamedEntity, Venue};
+    use chrono::Weekday;
+    use chrono_tz::{America::New_York, Europe::Prague};
+
+    #[test]
+    fn test_start_end_date_tz() {
+        let schedule = create_schedule("2020-03-02T00:30:00+01:00", "2020-03-03T00:00:00+01:00");
+        assert_eq!(schedule.start_date(Prague), NaiveDate::from_ymd(2020, 3, 2));
+        assert_eq!(schedule.end_date(Prague), NaiveDate::from_ymd(2020, 3, 3));
+        assert_eq!(schedule.start_date(New_York), NaiveDate::from_ymd(2020, 3, 1));
+        assert_eq!(schedule.end_date(New_York), NaiveDate::from_ymd(2020, 3, 2));
+    }
+
+    #[test]
+    fn test_start_of_day_dst() {
+        // Europe switches to DST on 2020-03-29 and back on 2020-10-25, both after midnight
+        let date = NaiveDate::from_ymd(2020, 3, 29);
+        assert_eq!(start_of_day(date, Prague).to_rfc3339(), "2020-03-29T00:00:00+01:00");
+        let date = NaiveDate::from_ymd(2020, 3, 30);
+        assert_eq!(start_of_day(date, Prague).to_rfc3339(), "2020-03-30T00:00:00+02:00");
+        let date = NaiveDate::from_ymd(2020, 10, 26);
+        assert_eq!(start_of_day(date, Prague).to_rfc3339(), "2020-10-26T00:00:00+01:00");
+        // Santiago switches to DST at midnight, day 2019-09-08 starts at 01:00
+        let date = NaiveDate::from_ymd(2019, 9, 8);
+        let santiago = chrono_tz::America::Santiago;
+        assert_eq!(start_of_day(date, santiago).to_rfc3339(), "2019-09-08T01:00:00-03:00");
+    }
+
+    #[test]
+    fn test_split_across_dst_begin() {
+        let schedule = create_schedule("2020-03-01T10:00:00+01:00", "2020-03-30T00:00:00+02:00");
+        let events = generate_events_split(vec![schedule], &create_cal_req(Prague));
+        assert_dates(&events[0], "20200301", "20200302");
+        assert_dates(&events[1], "20200329", "20200330");
+    }
+
+    #[test]
+    fn test_split_across_dst_end() {
+        let schedule = create_schedule("2020-10-25T10:00:00+01:00", "2020-11-01T00:00:00+01:00");
+        let events = generate_events_split(vec![schedule], &create_cal_req(Prague));
+        assert_dates(&events[0], "20201025", "20201026");
+        assert_dates(&events[1], "20201031", "20201101");
+    }
+
+    #[test]
+    fn test_aggregate_across_dst_end() {
+        let schedules = vec![
+            create_schedule("2020-10-24T10:00:00+02:00", "2020-10-26T00:00:00+01:00"),
+            create_schedule("2020-10-25T00:00:00+02:00", "2020-10-27T00:00:00+01:00"),
+        ];
+        let events = generate_events_aggregate(schedules, &create_cal_req(Prague));
+        assert_eq!(events.len(), 3);
+        assert_dates(&events[0], "20201024", "20201025");
+        assert_dates(&events[1], "20201025", "20201026");
+        assert_dates(&events[2], "20201026", "20201027");
+    }
+
+    #[test]
+    fn test_aggregate_other_tz() {
+        // Both schedules start on the same day in Prague, but on different days in New York
+        let schedules = vec![
+            create_schedule("2020-01-10T00:00:00+01:00", "2020-01-20T00:00:00+01:00"),
+            create_schedule("2020-01-10T12:00:00+01:00", "2020-01-20T00:00:00+01:00"),
+        ];
+        let events = generate_events_aggregate(schedules.clone(), &create_cal_req(Prague));
+        assert_eq!(events.len(), 1);
+        assert_dates(&events[0], "20200110", "20200120");
+
+        let events = generate_events_aggregate(schedules, &create_cal_req(New_York));
+        assert_eq!(events.len(), 2);
+        assert_dates(&events[0], "20200109", "20200110");
+        assert_dates(&events[1], "20200110", "20200119");
+    }
+
+    #[test]
+    fn test_aggregate_weekend() {
+        let schedules = vec![
+            // Friday to Tuesday, and Thursday to the next Thursday
+            create_schedule("2020-01-10T00:00:00+01:00", "2020-01-15T00:00:00+01:00"),
+            create_schedule("2020-01-16T00:00:00+01:00", "2020-01-24T00:00:00+01:00"),
+        ];
+        let mut cal_req = create_cal_req(Prague);
+        cal_req.filters.days = vec![Weekday::Sat, Weekday::Sun];
+        let events = generate_events_aggregate(schedules, &cal_req);
+        assert_eq!(events.len(), 2);
+        assert_dates(&events[0], "20200111", "20200113");
+        assert_dates(&events[1], "20200118", "20200120");
+        let second_weekend = events[1].to_string();
+        assert!(second_weekend.contains("SUMMARY:Event ()\r\n"), "{}", second_weekend);
+    }
+
+    #[test]
+    fn test_weekly() {
+        let schedules = vec![
+            create_schedule("2020-01-01T00:00:00+01:00", "2020-03-01T00:00:00+01:00"),
+            create_schedule("2020-01-15T00:00:00+01:00", "2020-01-20T00:00:00+01:00"),
+        ];
+        let mut cal_req = create_cal_req(Prague);
+        cal_req.after = Some("2020-01-15".to_string());
+        let events = generate_events_weekly(schedules, &cal_req);
+        // weeks starting on Mondays from 2020-01-13 to 2020-02-24
+        assert_eq!(events.len(), 7);
+        assert_dates(&events[0], "20200113", "20200114");
+        assert_dates(&events[6], "20200224", "20200225");
+        let first_week = events[0].to_string();
+        assert!(
+            first_week.contains("SUMMARY:1 beginning, 1 ending, 1 continued"),
+            "{}",
+            first_week
+        );
+        let uid = format!("UID:weekly/User#123/{}/2020-01-13@goout.net", feed_id(&cal_req));
+        assert!(first_week.replace("\r\n ", "").contains(&uid), "{}", first_week);
+    }
+
+    #[test]
+    fn test_feed_id() {
+        let mut cal_req = create_cal_req(Prague);
+        let feed = feed_id(&cal_req);
+        assert_eq!(feed.len(), 16);
+        assert_eq!(feed_id(&create_cal_req(Prague)), feed);
+        assert_ne!(feed_id(&create_cal_req(New_York)), feed);
+        cal_req.filters.max_price = Some(100.0);
+        assert_ne!(feed_id(&cal_req), feed);
+        // doesn't affect events
+        let mut cal_req = create_cal_req(Prague);
+        cal_req.after = Some("2020-01-15".to_string());
+        assert_eq!(feed_id(&cal_req), feed);
+    }
+
+    #[test]
+    fn test_recurring_weekly() {
+        let mut schedules = create_repeated_schedules(&[
+            "2020-01-07T19:00:00+01:00",
+            "2020-01-14T19:00:00+01:00",
+            "2020-01-21T19:00:00+01:00",
+        ]);
+        schedules[1].cancelled = true;
+        let (events, rest) = generate_events_recurring(schedules, &create_cal_req(Prague));
+        assert_eq!((events.len(), rest.len()), (1, 0));
+        let ical_string = events[0].to_string();
+        assert!(ical_string.contains("UID:recurring/Event#1@goout.net\r\n"), "{}", ical_string);
+        assert!(ical_string.contains("RRULE:FREQ=WEEKLY;COUNT=3\r\n"), "{}", ical_string);
+        assert!(ical_string.contains("EXDATE:20200114T180000Z\r\n"), "{}", ical_string);
+        assert!(!ical_string.contains("CANCELLED"), "{}", ical_string);
+    }
+
+    #[test]
+    fn test_recurring_across_dst() {
+        // same local time, but a different UTC one after the DST begins
+        let schedules = create_repeated_schedules(&[
+            "2020-03-21T19:00:00+01:00",
+            "2020-03-28T19:00:00+01:00",
+            "2020-04-04T19:00:00+02:00",
+        ]);
+        let (events, _) = generate_events_recurring(schedules, &create_cal_req(Prague));
+        let ical_string = events[0].to_string();
+        assert!(!ical_string.contains("RRULE"), "{}", ical_string);
+        assert!(
+            ical_string.contains("RDATE:20200328T180000Z,20200404T170000Z\r\n"),
+            "{}",
+            ical_string
+        );
+    }
+
+    #[test]
+    fn test_recurring_not_collapsible() {
+        let mut schedules =
+            create_repeated_schedules(&["2020-01-07T19:00:00+01:00", "2020-01-14T19:00:00+01:00"]);
+        schedules[1].venue_id = 2;
+        let (events, rest) = generate_events_recurring(schedules, &create_cal_req(Prague));
+        assert_eq!((events.len(), rest.len()), (0, 2));
+    }
+
+    #[test]
+    fn test_sameday_merge() {
+        let mut schedules = create_repeated_schedules(&[
+            "2020-01-07T20:00:00+01:00",
+            "2020-01-07T15:00:00+01:00",
+            "2020-01-08T15:00:00+01:00",
+        ]);
+        schedules[1].url = "https://goout.net/matinee/".to_string();
+        schedules[1].pricing = "100".to_string();
+        schedules[1].currency = "CZK".to_string();
+        let (events, rest) = generate_events_sameday(schedules, &create_cal_req(Prague));
+        assert_eq!((events.len(), rest.len()), (1, 1));
+        let ical_string = events[0].to_string();
+        assert!(ical_string.contains("DTSTART:20200107T140000Z\r\n"), "{}", ical_string);
+        assert!(ical_string.contains("DTEND:20200107T210000Z\r\n"), "{}", ical_string);
+        assert!(
+            ical_string.contains("UID:sameday/Event#1/2020-01-07@goout.net\r\n"),
+            "{}",
+            ical_string
+        );
+        assert!(
+            ical_string.contains(
+                "DESCRIPTION:15:00–17:00\\nCZK 100\\nhttps://goout.net/matinee/\\n\\n20:00"
+            ),
+            "{}",
+            ical_string
+        );
+
+        let mut schedules =
+            create_repeated_schedules(&["2020-01-07T20:00:00+01:00", "2020-01-07T15:00:00+01:00"]);
+        schedules[1].venue_id = 2;
+        let (events, rest) = generate_events_sameday(schedules, &create_cal_req(Prague));
+        assert_eq!((events.len(), rest.len()), (0, 2));
+    }
+
+    #[test]
+    fn test_distances() {
+        let mut cal_req = create_cal_req(Prague);
+        cal_req.homes = vec![HomeLocation {
+            name: "Home".to_string(),
+            coordinates: Coordinates { latitude: 50.0, longitude: 15.0 },
+        }];
+        let distance_line = "\\nDistance: Home 71 km";
+        let unfolded = |event: &CalendarEvent| event.to_string().replace("\r\n ", "");
+
+        let slots =
+            create_repeated_schedules(&["2020-01-07T15:00:00+01:00", "2020-01-07T20:00:00+01:00"]);
+        let (events, _) = generate_events_sameday(slots, &cal_req);
+        let sameday = unfolded(&events[0]);
+        assert_eq!(sameday.matches(distance_line).count(), 1, "{}", sameday);
+
+        let exhibitions = vec![
+            create_schedule("2020-01-01T00:00:00+01:00", "2020-03-01T00:00:00+01:00"),
+            create_schedule("2020-01-15T00:00:00+01:00", "2020-01-20T00:00:00+01:00"),
+        ];
+        let events = generate_events_lastchance(exhibitions.clone(), &cal_req);
+        let lastchance = unfolded(&events[0]);
+        assert!(lastchance.contains("2020-01-01 - 2020-02-29\\n\\n"), "{}", lastchance);
+        assert_eq!(lastchance.matches(distance_line).count(), 1, "{}", lastchance);
+
+        let events = generate_events_aggregate(exhibitions, &cal_req);
+        // the second event lists both schedules
+        let aggregate = unfolded(&events[1]);
+        assert_eq!(aggregate.matches(distance_line).count(), 2, "{}", aggregate);
+    }
+
+    /// Timed schedules of the same event at the same venue, lasting 2 hours.
+    /// Schedules of one event at one venue. Venues are not shared, like when coming from
+    /// different pages of the GoOut API.
+    fn create_repeated_schedules(starts: &[&str]) -> Vec<Schedule> {
+        starts
+            .iter()
+            .map(|start| {
+                let mut schedule = create_schedule(start, start);
+                schedule.end = schedule.start + Duration::hours(2);
+                schedule.is_long_term = false;
+                schedule
+            })
+            .collect()
+    }
+
+    fn assert_dates(ical_event: &IcalEvent, expected_start: &str, expected_end: &str) {
+        let ical_string = ical_event.to_string();
+        let start_line = format!("DTSTART;VALUE=DATE:{}\r\n", expected_start);
+        let end_line = format!("DTEND;VALUE=DATE:{}\r\n", expected_end);
+        assert!(ical_string.contains(&start_line), "{} not in {}", start_line, ical_string);
+        assert!(ical_string.contains(&end_line), "{} not in {}", end_line, ical_string);
+    }
+
+    #[test]
+    fn test_allday_timed() {
+        let mut schedule =
+            create_schedule("2020-01-10T00:00:00+01:00", "2020-01-13T00:00:00+01:00");
+        schedule.is_long_term = false;
+        schedule.hour_ignored = true;
+        let cal_req = create_cal_req(Prague);
+        let schedule = allday_timed(schedule, &cal_req);
+        let (events, rest) = generate_events_multiday(vec![schedule], &cal_req);
+        assert_eq!((events.len(), rest.len()), (3, 0));
+        let last_day = events[2].to_string();
+        assert!(last_day.contains("DTSTART:20200112T180000Z\r\n"), "{}", last_day);
+        assert!(last_day.contains("DTEND:20200112T210000Z\r\n"), "{}", last_day);
+        assert!(last_day.contains("DESCRIPTION:Approximate time"), "{}", last_day);
+    }
+
+    #[test]
+    fn test_permanent_once() {
+        let mut schedule =
+            create_schedule("2016-01-01T00:00:00+01:00", "2040-01-01T00:00:00+01:00");
+        schedule.permanent = true;
+        let mut cal_req = create_cal_req(Prague);
+        let today = Utc::now().with_timezone(&Prague).date().naive_local();
+        assert_eq!(permanent_once(schedule.clone(), &cal_req).start_date(Prague), today);
+
+        cal_req.after = Some("2020-03-01".to_string());
+        let once = permanent_once(schedule.clone(), &cal_req);
+        assert_eq!(once.start_date(Prague), NaiveDate::from_ymd(2020, 3, 1));
+        assert_eq!(once.days(Prague), 1);
+
+        // the first day if it is later than `after`
+        cal_req.after = Some("2015-03-01".to_string());
+        let once = permanent_once(schedule, &cal_req);
+        assert_eq!(once.start_date(Prague), NaiveDate::from_ymd(2016, 1, 1));
+    }
+
+    #[test]
+    fn test_multiday_split() {
+        let mut festival =
+            create_schedule("2020-03-27T18:00:00+01:00", "2020-03-29T23:00:00+02:00");
+        festival.is_long_term = false;
+        let mut night = create_schedule("2020-01-10T22:00:00+01:00", "2020-01-12T02:00:00+01:00");
+        night.is_long_term = false;
+        let mut concert = create_schedule("2020-01-07T20:00:00+01:00", "2020-01-07T23:00:00+01:00");
+        concert.is_long_term = false;
+        assert_eq!((festival.days(Prague), night.days(Prague), concert.days(Prague)), (3, 3, 1));
+
+        let schedules = vec![festival, night, concert];
+        let mut cal_req = create_cal_req(Prague);
+        // only schedules with made-up time are split by default
+        let (events, rest) = generate_events_multiday(schedules.clone(), &cal_req);
+        assert_eq!((events.len(), rest.len()), (0, 3));
+
+        cal_req.multiday = MultidayHandling::Split;
+        let (events, rest) = generate_events_multiday(schedules.clone(), &cal_req);
+        assert_eq!((events.len(), rest.len()), (5, 1));
+        // daily hours are kept in local time across the DST change
+        let last_day = events[2].to_string();
+        assert!(last_day.contains("DTSTART:20200329T160000Z\r\n"), "{}", last_day);
+        assert!(last_day.contains("DTEND:20200329T210000Z\r\n"), "{}", last_day);
+        assert!(last_day.contains("UID:multiday/Schedule#1585328400/2020-03-29@goout.net"));
+        // overnight schedule ends on the next day
+        let second_night = events[4].to_string();
+        assert!(second_night.contains("DTSTART:20200111T210000Z\r\n"), "{}", second_night);
+        assert!(second_night.contains("DTEND:20200112T010000Z\r\n"), "{}", second_night);
+
+        // days of the split schedules are filtered one by one
+        cal_req.filters.days = vec![Weekday::Sat];
+        let (events, rest) = generate_events_multiday(schedules, &cal_req);
+        assert_eq!((events.len(), rest.len()), (2, 1));
+        let festival_saturday = events[0].to_string();
+        assert!(
+            festival_saturday.contains("DTSTART:20200328T170000Z\r\n"),
+            "{}",
+            festival_saturday
+        );
+        let saturday_night = events[1].to_string();
+        assert!(saturday_night.contains("DTSTART:20200111T210000Z\r\n"), "{}", saturday_night);
+    }
+
+    fn create_cal_req(tz: Tz) -> CalendarRequest {
+        CalendarRequest {
+            id: 123,
+            language: Language::En,
+            after: None,
+            before: None,
+            longterm: LongtermHandling::Preserve,
+            longterm_min_days: None,
+            lastchance_days: 7,
+            permanent: PermanentHandling::Preserve,
+            tz,
+            alarm: None,
+            alarm_allday: None,
+            colors: CategoryColors::default(),
+            uids: UidScheme::V2,
+            format: OutputFormat::Ical,
+            csv_bom: false,
+            busy_allday: false,
+            busy_longterm: false,
+            repeats: RepeatHandling::Separate,
+            samedays: SamedayHandling::Separate,
+            multiday: MultidayHandling::Preserve,
+            allday: AlldayHandling::Preserve,
+            allday_time: NaiveTime::from_hms(19, 0, 0),
+            allday_duration: Duration::hours(3),
+            filters: ScheduleFilters::default(),
+            homes: Vec::new(),
+        }
+    }
+
+    fn create_schedule(start: &str, end: &str) -> Schedule {
+        let start = DateTime::parse_from_rfc3339(start).unwrap();
+        let event = Event {
+            name: "Event".to_string(),
+            text: "Text".to_string(),
+            categories: BTreeMap::new(),
+            main_image: None,
+        };
+        let venue = Venue {
+            name: "Venue".to_string(),
+            address: "Address".to_string(),
+            city: "City".to_string(),
+            latitude: 50.0,
+            longitude: 14.0,
+            locality: Locality { country: NamedEntity { name: "Country".to_string() } },
+        };
+        Schedule {
+            id: start.timestamp() as u64,
+            event_id: 1,
+            event: Rc::new(event),
+            url: "https://goout.net/".to_string(),
+            cancelled: false,
+            start,
+            end: DateTime::parse_from_rfc3339(end).unwrap(),
+            uploaded_on: start,
+            hour_ignored: false,
+            is_long_term: true,
+            permanent: false,
+            approximate_time: false,
+            pricing: String::new(),
+            currency: String::new(),
+            timezone: None,
+            venue_id: 1,
+            venue: Rc::new(venue),
+            performers: Vec::new(),
+        }
+    }
+}
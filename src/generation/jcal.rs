@@ -0,0 +1,207 @@
+//! Serialisation of calendars to jCal, the JSON format for iCalendar (RFC 7265).
+
+use super::tree::{recur_parts, Component, Property, ValueType};
+use serde_json::{json, Map, Number, Value};
+
+pub(super) fn render(calendar: &Component) -> String {
+    serde_json::to_string(&component_to_json(calendar)).expect("Serialising Value should not fail.")
+}
+
+fn component_to_json(component: &Component) -> Value {
+    let properties: Vec<Value> = component.properties.iter().map(property_to_json).collect();
+    let components: Vec<Value> = component.components.iter().map(component_to_json).collect();
+    json!([component.name.to_lowercase(), properties, components])
+}
+
+fn property_to_json(property: &Property) -> Value {
+    let params: Map<String, Value> = property
+        .params()
+        .map(|(name, value)| (name.to_lowercase(), Value::String(value.clone())))
+        .collect();
+    let value_type = property.value_type();
+    let values: Vec<Value> =
+        property.values().into_iter().map(|value| value_to_json(value, value_type)).collect();
+
+    let mut result =
+        vec![json!(property.name.to_lowercase()), json!(params), json!(value_type.name())];
+    if property.is_structured() {
+        result.push(Value::Array(values));
+    } else {
+        result.extend(values);
+    }
+    Value::Array(result)
+}
+
+fn value_to_json(value: String, value_type: ValueType) -> Value {
+    if value_type == ValueType::Recur {
+        return recur_to_json(&value);
+    }
+    let number = match value_type {
+        ValueType::Integer => value.parse::<i64>().ok().map(Number::from),
+        ValueType::Float => value.parse::<f64>().ok().and_then(Number::from_f64),
+        _ => None,
+    };
+    number.map_or(Value::String(value), Value::Number)
+}
+
+/// Recurrence rule as an object, with numeric parts as numbers and lists as arrays.
+fn recur_to_json(value: &str) -> Value {
+    let parts: Map<String, Value> = recur_parts(value)
+        .into_iter()
+        .map(|(name, values)| {
+            let mut values: Vec<Value> = values
+                .into_iter()
+                .map(|v| v.parse::<i64>().map_or_else(|_| json!(v), |number| json!(number)))
+                .collect();
+            let json_value =
+                if values.len() == 1 { values.remove(0) } else { Value::Array(values) };
+            (name, json_value)
+        })
+        .collect();
+    Value::Object(parts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tree::{self, SemanticComponent, SemanticProperty};
+    use super::*;
+    use std::ffi::OsStr;
+    use std::fs;
+
+    /// Convert jCal back to the component content; inverse of component_to_json().
+    fn component_from_json(json: &Value) -> SemanticComponent {
+        let parts = json.as_array().unwrap();
+        SemanticComponent {
+            name: parts[0].as_str().unwrap().to_uppercase(),
+            properties: parts[1].as_array().unwrap().iter().map(property_from_json).collect(),
+            components: parts[2].as_array().unwrap().iter().map(component_from_json).collect(),
+        }
+    }
+
+    fn property_from_json(json: &Value) -> SemanticProperty {
+        let parts = json.as_array().unwrap();
+        let params = parts[1]
+            .as_object()
+            .unwrap()
+            .iter()
+            .map(|(name, value)| (name.to_uppercase(), value.as_str().unwrap().to_string()))
+            .collect();
+        let value_type = match parts[2].as_str().unwrap() {
+            "date" => ValueType::Date,
+            "date-time" => ValueType::DateTime,
+            "duration" => ValueType::Duration,
+            "float" => ValueType::Float,
+            "integer" => ValueType::Integer,
+            "recur" => ValueType::Recur,
+            "text" => ValueType::Text,
+            "uri" => ValueType::Uri,
+            other => panic!("Unexpected value type {}", other),
+        };
+        let values = match &parts[3..] {
+            [Value::Array(structured)] => structured.clone(),
+            values => values.to_vec(),
+        };
+        let values = values
+            .iter()
+            .map(|value| match value {
+                Value::String(string) => string.clone(),
+                Value::Object(recur) => recur_from_json(recur),
+                other => other.to_string(),
+            })
+            .collect();
+        SemanticProperty {
+            name: parts[0].as_str().unwrap().to_uppercase(),
+            params,
+            value_type,
+            values,
+        }
+    }
+
+    fn recur_from_json(recur: &Map<String, Value>) -> String {
+        let part_value = |value: &Value| match value {
+            Value::Array(values) => values.iter().map(|v| v.to_string()).collect::<Vec<_>>(),
+            other => vec![other.to_string()],
+        };
+        recur
+            .iter()
+            .map(|(name, value)| {
+                let values = part_value(value).join(",").replace('"', "");
+                format!("{}={}", name.to_uppercase(), values)
+            })
+            .collect::<Vec<_>>()
+            .join(";")
+    }
+
+    #[test]
+    fn test_equivalent_to_ical() {
+        for entry in fs::read_dir("test_data").unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension() != Some(OsStr::new("ical")) {
+                continue;
+            }
+            let ical = fs::read_to_string(&path).unwrap();
+            let calendar = tree::parse(&ical);
+            let jcal: Value = serde_json::from_str(&render(&calendar)).unwrap();
+            assert_eq!(component_from_json(&jcal), calendar.semantic(), "{}", path.display());
+        }
+    }
+
+    #[test]
+    fn test_render() {
+        let calendar = tree::parse(
+            "BEGIN:VCALENDAR\r\nVERSION:2.0\r\nBEGIN:VEVENT\r\nDTSTART;VALUE=DATE:20160101\r\n\
+             DTEND:20200302T230000Z\r\nGEO:52.5063;13.4436\r\nSUMMARY:A\\, B\\nC\r\n\
+             RRULE:FREQ=WEEKLY;COUNT=3\r\nBEGIN:VALARM\r\nACTION:DISPLAY\r\n\
+             TRIGGER;RELATED=END:-P1DT15H\r\nEND:VALARM\r\nEND:VEVENT\r\nEND:VCALENDAR\r\n",
+        );
+        let jcal: Value = serde_json::from_str(&render(&calendar)).unwrap();
+        assert_eq!(
+            jcal,
+            json!([
+                "vcalendar",
+                [["version", {}, "text", "2.0"]],
+                [[
+                    "vevent",
+                    [
+                        ["dtstart", {}, "date", "2016-01-01"],
+                        ["dtend", {}, "date-time", "2020-03-02T23:00:00Z"],
+                        ["geo", {}, "float", [52.5063, 13.4436]],
+                        ["summary", {}, "text", "A, B\nC"],
+                        ["rrule", {}, "recur", {"freq": "WEEKLY", "count": 3}],
+                    ],
+                    [[
+                        "valarm",
+                        [
+                            ["action", {}, "text", "DISPLAY"],
+                            ["trigger", {"related": "END"}, "duration", "-P1DT15H"],
+                        ],
+                        [],
+                    ]],
+                ]],
+            ])
+        );
+    }
+
+    #[test]
+    fn test_property_to_json() {
+        let calendar = tree::parse(
+            "BEGIN:VEVENT\r\nDTSTART;VALUE=DATE:20160101\r\nGEO:52.5063;13.4436\r\n\
+             CATEGORIES:A,B\r\nSEQUENCE:2\r\nTRIGGER;RELATED=END:-P1D\r\n\
+             RRULE:FREQ=WEEKLY;INTERVAL=2;BYDAY=MO,TU\r\nRDATE:20200302T190000Z,20200309T190000Z\r\n\
+             END:VEVENT\r\n",
+        );
+        let json: Vec<Value> = calendar.properties.iter().map(property_to_json).collect();
+        assert_eq!(
+            Value::Array(json),
+            json!([
+                ["dtstart", {}, "date", "2016-01-01"],
+                ["geo", {}, "float", [52.5063, 13.4436]],
+                ["categories", {}, "text", "A", "B"],
+                ["sequence", {}, "integer", 2],
+                ["trigger", {"related": "END"}, "duration", "-P1D"],
+                ["rrule", {}, "recur", {"freq": "WEEKLY", "interval": 2, "byday": ["MO", "TU"]}],
+                ["rdate", {}, "date-time", "2020-03-02T19:00:00Z", "2020-03-09T19:00:00Z"],
+            ])
+        );
+    }
+}
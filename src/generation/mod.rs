@@ -1,8 +1,13 @@
-use crate::{calendar::CalendarRequest, error::HandlerResult};
+use crate::{
+    calendar::{CalendarRequest, Coordinates, FeedFormat, OutputFormat, ScheduleFilters},
+    digest::{DigestPeriod, DigestView},
+    error::HandlerResult,
+};
 use anyhow::{anyhow, Context};
 use attohttpc;
-use chrono::Duration;
-use icalendar::Calendar;
+use chrono::{Duration, NaiveDate, NaiveTime};
+use chrono_tz::Tz;
+use ical::ScheduleHelper;
 #[cfg(test)]
 use mockito;
 use serde::Deserialize;
@@ -10,11 +15,23 @@ use serde_json::Value;
 use std::collections::{BTreeMap, HashMap};
 use std::rc::Rc;
 
+mod agenda;
+mod api;
+mod digest;
+mod feed;
+mod freebusy;
+mod geojson;
 pub mod ical;
+mod jcal;
+mod sequence;
+mod spreadsheet;
+mod tree;
+mod xcal;
 
 type DateTime = chrono::DateTime<chrono::FixedOffset>;
 
 const ENDPOINT_PATH: &str = "/services/feeder/v1/events.json";
+const IMAGE_SIZE: &str = "800";
 
 #[derive(Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
@@ -31,10 +48,16 @@ struct ScheduleOnWire {
     uploaded_on: DateTime,
     hour_ignored: bool,
     is_long_term: bool,
+    // permanent expositions, e.g. of a museum, are long-term schedules spanning many years
+    #[serde(default)]
+    permanent: bool,
     pricing: String,
     // rarely, some schedules don't contain currency key, e.g. qhstd
     #[serde(default)]
     currency: String,
+    // IANA name, missing in older responses
+    #[serde(default)]
+    timezone: Option<String>,
     venue_id: u64,
     performer_ids: Vec<u64>,
 }
@@ -42,6 +65,7 @@ struct ScheduleOnWire {
 #[derive(Clone, Debug)]
 struct Schedule {
     id: u64,
+    event_id: u64,
     event: Rc<Event>,
     url: String,
     cancelled: bool,
@@ -50,17 +74,48 @@ struct Schedule {
     uploaded_on: DateTime,
     hour_ignored: bool,
     is_long_term: bool,
+    permanent: bool,
+    /// Time of day made up by us for an hour-ignored schedule.
+    approximate_time: bool,
     pricing: String,
     currency: String,
+    /// Local timezone of the schedule, if GoOut gives a known one.
+    timezone: Option<Tz>,
+    venue_id: u64,
     venue: Rc<Venue>,
     performers: Vec<Rc<Performer>>,
 }
 
+impl Schedule {
+    /// Local timezone of the schedule, `feed_tz` if unknown.
+    fn local_tz(&self, feed_tz: Tz) -> Tz {
+        self.timezone.unwrap_or(feed_tz)
+    }
+}
+
 #[derive(Clone, Deserialize, Debug)]
 struct NamedEntity {
     name: String,
 }
 
+#[derive(Clone, Deserialize, Debug)]
+struct Category {
+    name: String, // "Koncerty"
+    #[serde(rename = "enum", default)]
+    kind: String, // "CONCERTS"
+}
+
+#[derive(Clone, Deserialize, Debug)]
+struct Image {
+    src: String, // "https://goout.net/i/056/562798-%%%.jpg", %%% stands for size
+}
+
+impl Image {
+    fn url(&self) -> String {
+        self.src.replace("%%%", IMAGE_SIZE)
+    }
+}
+
 #[derive(Deserialize, Debug)]
 struct Locality {
     country: NamedEntity,
@@ -76,6 +131,12 @@ struct Venue {
     locality: Locality,
 }
 
+impl Venue {
+    const fn coordinates(&self) -> Coordinates {
+        Coordinates { latitude: self.latitude, longitude: self.longitude }
+    }
+}
+
 #[derive(Deserialize, Debug)]
 struct Performer {
     name: String,
@@ -84,9 +145,11 @@ struct Performer {
 
 #[derive(Clone, Deserialize, Debug)]
 struct Event {
-    name: String,                           // "Hudební ceny Apollo 2018"
-    text: String,                           // "Apollo Czech Music Critics Awards for ..."
-    categories: BTreeMap<u64, NamedEntity>, // BTreeMap because we want stable order
+    name: String,                        // "Hudební ceny Apollo 2018"
+    text: String,                        // "Apollo Czech Music Critics Awards for ..."
+    categories: BTreeMap<u64, Category>, // BTreeMap because we want stable order
+    #[serde(rename = "mainImage", default)]
+    main_image: Option<Image>,
 }
 
 // Instruct serde to use default values for fields not present when deserializing. This is because
@@ -128,7 +191,7 @@ fn fetch_page(cal_req: &CalendarRequest, page: u8) -> HandlerResult<EventsRespon
         ("tag", "liked"),
         ("user", user_str),
         ("page", page_str),
-        ("language", &cal_req.language),
+        ("language", cal_req.language.code()),
         ("source", "goout.strohel.eu"),
     ];
     if let Some(after) = &cal_req.after {
@@ -191,6 +254,7 @@ fn response_to_schedules(response: EventsResponse) -> HandlerResult<Vec<Schedule
         })?);
         let schedule = Schedule {
             id: on_wire.id,
+            event_id: on_wire.event_id,
             event,
             url: on_wire.url,
             cancelled: on_wire.cancelled,
@@ -200,8 +264,12 @@ fn response_to_schedules(response: EventsResponse) -> HandlerResult<Vec<Schedule
             uploaded_on: on_wire.uploaded_on,
             hour_ignored: on_wire.hour_ignored,
             is_long_term: on_wire.is_long_term,
+            permanent: on_wire.permanent,
+            approximate_time: false,
             pricing: on_wire.pricing,
             currency: on_wire.currency,
+            timezone: on_wire.timezone.and_then(|name| name.parse().ok()),
+            venue_id: on_wire.venue_id,
             venue,
             performers,
         };
@@ -210,7 +278,7 @@ fn response_to_schedules(response: EventsResponse) -> HandlerResult<Vec<Schedule
     Ok(result)
 }
 
-pub(in crate) fn generate(cal_req: &CalendarRequest) -> HandlerResult<String> {
+fn fetch_schedules(cal_req: &CalendarRequest) -> HandlerResult<Vec<Schedule>> {
     let mut schedules = Vec::<Schedule>::new();
     for page in 1.. {
         let events_response = fetch_page(cal_req, page)?;
@@ -221,11 +289,249 @@ pub(in crate) fn generate(cal_req: &CalendarRequest) -> HandlerResult<String> {
             break;
         }
     }
+    if let Some(before) = cal_req.before {
+        schedules.retain(|s| s.start_date(cal_req.tz) < before);
+    }
+    if let Some(min_days) = cal_req.longterm_min_days {
+        for schedule in schedules.iter_mut() {
+            schedule.is_long_term = schedule.days(cal_req.tz) >= i64::from(min_days);
+        }
+    }
+    schedules
+        .retain(|s| passes_filters(s, &cal_req.filters) && passes_day_time_filters(s, cal_req));
+    Ok(schedules)
+}
+
+/// Whether `schedule` passes the days of week and time of day filters in `cal_req`. All-day
+/// schedules pass if any of their days (in `tz` of the feed) is allowed, the split and aggregate
+/// long-term modes then emit only the allowed days. Timed schedules split into days by the multiday
+/// mode are filtered per day by generate_events_multiday().
+fn passes_day_time_filters(schedule: &Schedule, cal_req: &CalendarRequest) -> bool {
+    let (filters, tz) = (&cal_req.filters, cal_req.tz);
+    if ical::is_all_day(schedule) {
+        let start_date = schedule.start_date(tz);
+        // a week contains all days
+        let days = schedule.days(tz).min(7);
+        return (0..days).any(|day| filters.allows_day(start_date + Duration::days(day)));
+    }
+    if ical::is_split_per_day(schedule, cal_req) {
+        return true;
+    }
+    passes_timed_filters(schedule.start, schedule.end, schedule.local_tz(tz), filters)
+}
+
+/// Whether a timed schedule from `start` to `end` passes the days of week and time of day
+/// `filters`, evaluated in `tz`. A window from `from_time` to an earlier `to_time` spans midnight
+/// and belongs to the day it starts on, e.g. 01:00 on Saturday is in the Friday window from 18:00 to
+/// 02:00.
+fn passes_timed_filters(start: DateTime, end: DateTime, tz: Tz, filters: &ScheduleFilters) -> bool {
+    let local_start = start.with_timezone(&tz);
+    let (start_date, start_time) = (local_start.date().naive_local(), local_start.time());
+    let from_time = filters.from_time.unwrap_or_else(|| NaiveTime::from_hms(0, 0, 0));
+    let overnight = matches!(filters.to_time, Some(to_time) if to_time <= from_time);
+    let window_date = if start_time >= from_time {
+        start_date
+    } else if matches!(filters.to_time, Some(to_time) if overnight && start_time < to_time) {
+        start_date.pred()
+    } else {
+        return false;
+    };
+    let to_date = if overnight { window_date.succ() } else { window_date };
+    let ends_late = |to_time| end > ical::local_datetime(to_date, to_time, tz);
+    filters.allows_day(window_date)
+        && !matches!(filters.to_time, Some(to_time) if ends_late(to_time))
+}
+
+fn passes_filters(schedule: &Schedule, filters: &ScheduleFilters) -> bool {
+    let kinds: Vec<String> =
+        schedule.event.categories.values().map(|c| c.kind.to_lowercase()).collect();
+    let venue = &schedule.venue;
+    let city = venue.city.to_lowercase();
+    let country = venue.locality.country.name.to_lowercase();
+    (filters.categories.is_empty() || filters.categories.iter().any(|c| kinds.contains(c)))
+        && !filters.exclude_categories.iter().any(|c| kinds.contains(c))
+        && (filters.venues.is_empty() || filters.venues.contains(&venue.name.to_lowercase()))
+        && (filters.cities.is_empty()
+            || filters.cities.iter().any(|c| city == *c || city.starts_with(&format!("{} ", c))))
+        && (filters.countries.is_empty() || filters.countries.contains(&country))
+        && match (filters.max_price, lowest_price(&schedule.pricing)) {
+            (Some(max_price), Some(price)) => price <= max_price,
+            _ => true,
+        }
+        && match filters.near {
+            Some((centre, radius_km)) => distance_km(centre, venue.coordinates()) <= radius_km,
+            None => true,
+        }
+}
+
+/// Great-circle distance between `a` and `b` in kilometres, using the haversine formula.
+fn distance_km(a: Coordinates, b: Coordinates) -> f64 {
+    const EARTH_RADIUS_KM: f64 = 6371.0;
+    let (latitude_a, latitude_b) = (a.latitude.to_radians(), b.latitude.to_radians());
+    let half_latitude_diff = (latitude_b - latitude_a) / 2.0;
+    let half_longitude_diff = (b.longitude - a.longitude).to_radians() / 2.0;
+    let haversine = (latitude_a.cos() * latitude_b.cos())
+        .mul_add(half_longitude_diff.sin().powi(2), half_latitude_diff.sin().powi(2));
+    2.0 * EARTH_RADIUS_KM * haversine.sqrt().asin()
+}
+
+/// Lowest number in GoOut `pricing`, e.g. 130 for "130–300" and 1290 for "1 290 Kč". Spaces and
+/// commas followed by a group of 3 digits separate thousands, other commas are decimal.
+fn lowest_price(pricing: &str) -> Option<f64> {
+    let chars: Vec<char> = pricing.chars().collect();
+    let is_digit_at = |i: usize| matches!(chars.get(i), Some(c) if c.is_ascii_digit());
+    let thousands_group_at = |i: usize| (i..i + 3).all(is_digit_at) && !is_digit_at(i + 3);
+
+    let mut numbers: Vec<String> = Vec::new();
+    let mut number = String::new();
+    for (i, &c) in chars.iter().enumerate() {
+        let in_number = !number.is_empty();
+        match c {
+            '0'..='9' => number.push(c),
+            ' ' | '\u{a0}' | '\u{202f}' | ',' if in_number && thousands_group_at(i + 1) => {}
+            '.' | ',' if in_number && !number.contains('.') && is_digit_at(i + 1) => {
+                number.push('.')
+            }
+            _ if in_number => numbers.push(std::mem::take(&mut number)),
+            _ => {}
+        }
+    }
+    numbers.push(number);
+    numbers
+        .iter()
+        .filter_map(|number| number.parse::<f64>().ok())
+        .fold(None, |lowest, price| Some(lowest.map_or(price, |lowest: f64| lowest.min(price))))
+}
+
+/// Escape `text` for use in XML element content and attribute values.
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+/// Fetch schedules sorted by start (and ID for stable order).
+fn fetch_sorted_schedules(cal_req: &CalendarRequest) -> HandlerResult<Vec<Schedule>> {
+    let mut schedules = fetch_schedules(cal_req)?;
+    schedules.sort_by_key(|s| (s.start, s.id));
+    Ok(schedules)
+}
+
+/// Generate iCalendar, which is also the source for other representations of the calendar.
+fn generate_ical(cal_req: &CalendarRequest) -> HandlerResult<String> {
+    let schedules = fetch_schedules(cal_req)?;
+    let events = ical::generate_events(schedules, cal_req);
+    // Failure to persist sequences shouldn't prevent serving the calendar.
+    if let Err(e) = sequence::persist() {
+        eprintln!("Cannot persist sequence store: {:?}", e);
+    }
+    Ok(ical::render_calendar(&events, cal_req))
+}
+
+pub(in crate) fn generate(cal_req: &CalendarRequest) -> HandlerResult<String> {
+    Ok(match cal_req.format {
+        OutputFormat::Ical => generate_ical(cal_req)?,
+        OutputFormat::Jcal => jcal::render(&tree::parse(&generate_ical(cal_req)?)),
+        OutputFormat::Xcal => xcal::render(&tree::parse(&generate_ical(cal_req)?)),
+        OutputFormat::Csv => spreadsheet::render(&fetch_sorted_schedules(cal_req)?, cal_req),
+        OutputFormat::Geojson => geojson::render(&fetch_sorted_schedules(cal_req)?),
+        OutputFormat::Freebusy => freebusy::render(&fetch_schedules(cal_req)?, cal_req),
+    })
+}
 
-    let mut calendar = Calendar::new();
-    for event in ical::generate_events(schedules, cal_req) {
-        calendar.push(event);
+pub(in crate) fn generate_agenda(cal_req: &CalendarRequest) -> HandlerResult<String> {
+    let calendar = generate_ical(cal_req)?;
+    Ok(agenda::render(&tree::parse(&calendar), cal_req))
+}
+
+/// Render "what's on" digest of schedules in `period` beginning on date `from`.
+pub(in crate) fn generate_digest(
+    cal_req: &CalendarRequest,
+    from: NaiveDate,
+    period: DigestPeriod,
+    view: DigestView,
+) -> HandlerResult<String> {
+    let schedules = fetch_sorted_schedules(cal_req)?;
+    Ok(digest::render(&schedules, from, period, view, cal_req))
+}
+
+/// Render `page` (1-based) of schedules as JSON, with `per_page` schedules per page.
+pub(in crate) fn generate_schedules_json(
+    cal_req: &CalendarRequest,
+    page: usize,
+    per_page: usize,
+) -> HandlerResult<String> {
+    // sorted so that paging is stable
+    let schedules = fetch_sorted_schedules(cal_req)?;
+    Ok(api::render(&schedules, page, per_page))
+}
+
+pub(in crate) fn generate_feed(
+    cal_req: &CalendarRequest,
+    feed_format: FeedFormat,
+) -> HandlerResult<String> {
+    let schedules = fetch_sorted_schedules(cal_req)?;
+    Ok(match feed_format {
+        FeedFormat::Rss => feed::render_rss(&schedules, cal_req),
+        FeedFormat::Atom => feed::render_atom(&schedules, cal_req),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_passes_timed_filters() {
+        use chrono::Weekday;
+        use chrono_tz::{America::New_York, Europe::Prague};
+
+        let passes = |start: &str, end: &str, tz: Tz, filters: &ScheduleFilters| {
+            let (start, end) =
+                (DateTime::parse_from_rfc3339(start), DateTime::parse_from_rfc3339(end));
+            passes_timed_filters(start.unwrap(), end.unwrap(), tz, filters)
+        };
+        let mut evenings = ScheduleFilters {
+            from_time: Some(NaiveTime::from_hms(18, 0, 0)),
+            to_time: Some(NaiveTime::from_hms(2, 0, 0)),
+            ..ScheduleFilters::default()
+        };
+        // Friday evening, Saturday early morning (still Friday night), Saturday morning
+        let friday = ("2020-01-10T20:00:00+01:00", "2020-01-10T23:00:00+01:00");
+        let night = ("2020-01-11T01:00:00+01:00", "2020-01-11T02:00:00+01:00");
+        let morning = ("2020-01-11T09:00:00+01:00", "2020-01-11T11:00:00+01:00");
+        assert!(passes(friday.0, friday.1, Prague, &evenings));
+        assert!(passes(night.0, night.1, Prague, &evenings));
+        assert!(!passes(morning.0, morning.1, Prague, &evenings));
+        // ends after the window
+        assert!(!passes(friday.0, "2020-01-11T03:00:00+01:00", Prague, &evenings));
+
+        evenings.days = vec![Weekday::Fri];
+        assert!(passes(night.0, night.1, Prague, &evenings));
+        evenings.days = vec![Weekday::Sat];
+        assert!(!passes(night.0, night.1, Prague, &evenings));
+
+        // evaluated in the given (local) timezone: 19:00-22:00 in New York
+        let new_york = ("2020-01-11T01:00:00+01:00", "2020-01-11T04:00:00+01:00");
+        let same_day = ScheduleFilters {
+            from_time: Some(NaiveTime::from_hms(18, 0, 0)),
+            to_time: Some(NaiveTime::from_hms(23, 0, 0)),
+            ..ScheduleFilters::default()
+        };
+        assert!(passes(new_york.0, new_york.1, New_York, &same_day));
+        assert!(!passes(new_york.0, new_york.1, Prague, &same_day));
     }
 
-    Ok(calendar.to_string())
+    #[test]
+    fn test_lowest_price() {
+        assert_eq!(lowest_price(""), None);
+        assert_eq!(lowest_price("Free"), None);
+        assert_eq!(lowest_price("130–300"), Some(130.0));
+        assert_eq!(lowest_price("69.65"), Some(69.65));
+        assert_eq!(lowest_price("12,50"), Some(12.5));
+        assert_eq!(lowest_price("1 290 Kč"), Some(1290.0));
+        assert_eq!(lowest_price("1\u{a0}290–2\u{a0}500"), Some(1290.0));
+        assert_eq!(lowest_price("1,290"), Some(1290.0));
+        assert_eq!(lowest_price("1,290.50"), Some(1290.5));
+        assert_eq!(lowest_price("2 500, 1 290"), Some(1290.0));
+        assert_eq!(lowest_price("300 - 450"), Some(300.0));
+    }
 }
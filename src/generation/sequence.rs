@@ -0,0 +1,225 @@
+use crate::error::HandlerResult;
+use anyhow::Context;
+use chrono::Utc;
+#[cfg(not(test))]
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+#[cfg(not(test))]
+use std::sync::Mutex;
+use std::{env, fs};
+
+/// Environment variable with path to the file where the store is persisted.
+const STORE_PATH_VAR: &str = "SEQUENCE_STORE_PATH";
+const DEFAULT_STORE_PATH: &str = "sequences.json";
+/// Entries not used for this many days are dropped when the store is persisted. Events are served
+/// only until they end, so their entries aren't needed after that.
+const MAX_UNUSED_DAYS: i64 = 90;
+
+#[cfg(not(test))]
+static STORE: Lazy<Mutex<SequenceStore>> = Lazy::new(|| Mutex::new(SequenceStore::load()));
+
+#[cfg(not(test))]
+fn with_store<T>(f: impl FnOnce(&mut SequenceStore) -> T) -> T {
+    f(&mut STORE.lock().expect("Sequence store lock poisoned."))
+}
+
+// Each test runs in its own thread and local Rocket client dispatches requests in the calling
+// thread, so a thread-local store isolates tests from each other.
+#[cfg(test)]
+fn with_store<T>(f: impl FnOnce(&mut SequenceStore) -> T) -> T {
+    thread_local! {
+        static STORE: std::cell::RefCell<SequenceStore> = Default::default();
+    }
+    STORE.with(|store| f(&mut store.borrow_mut()))
+}
+
+/// Get SEQUENCE for a calendar component identified by `key` with content summarised by
+/// `fingerprint`. The sequence is increased each time the fingerprint changes.
+pub(super) fn get(key: &str, fingerprint: u64) -> u32 {
+    with_store(|store| store.sequence(key, fingerprint, today(), sequence_floor()))
+}
+
+/// Persist the sequence store if it has changed since it was loaded or last persisted.
+pub(super) fn persist() -> HandlerResult<()> {
+    with_store(|store| store.save(today()))
+}
+
+/// Days since the Unix epoch.
+fn today() -> i64 {
+    Utc::now().timestamp() / 86_400
+}
+
+/// Minutes since the Unix epoch. New and changed entries get at least this sequence, so that it
+/// keeps increasing even when the store is lost, e.g. on a fresh container.
+#[cfg(not(test))]
+fn sequence_floor() -> u32 {
+    (Utc::now().timestamp() / 60) as u32
+}
+
+// Served calendars need to be reproducible in tests.
+#[cfg(test)]
+const fn sequence_floor() -> u32 {
+    0
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct Entry {
+    fingerprint: u64,
+    sequence: u32,
+    /// Day (since the Unix epoch) the entry was last used.
+    last_used: i64,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct SequenceStore {
+    entries: HashMap<String, Entry>,
+    #[serde(skip)]
+    dirty: bool,
+}
+
+impl SequenceStore {
+    #[cfg(not(test))]
+    fn load() -> Self {
+        let path = match store_path() {
+            Some(path) => path,
+            None => return Self::default(),
+        };
+        // Missing or corrupt store only means that sequences start from scratch, don't fail.
+        match fs::read_to_string(&path).map(|json| serde_json::from_str(&json)) {
+            Ok(Ok(store)) => store,
+            Ok(Err(e)) => {
+                eprintln!("Ignoring corrupt sequence store {}: {}", path, e);
+                Self::default()
+            }
+            Err(_) => Self::default(),
+        }
+    }
+
+    fn sequence(&mut self, key: &str, fingerprint: u64, today: i64, floor: u32) -> u32 {
+        if let Some(entry) = self.entries.get_mut(key) {
+            if entry.fingerprint != fingerprint {
+                entry.fingerprint = fingerprint;
+                entry.sequence = (entry.sequence + 1).max(floor);
+                self.dirty = true;
+            }
+            // at most once a day, so that each request doesn't rewrite the store
+            if entry.last_used != today {
+                entry.last_used = today;
+                self.dirty = true;
+            }
+            return entry.sequence;
+        }
+
+        let entry = Entry { fingerprint, sequence: floor, last_used: today };
+        self.entries.insert(key.to_string(), entry);
+        self.dirty = true;
+        floor
+    }
+
+    /// Drop entries not used for more than MAX_UNUSED_DAYS before `today`.
+    fn prune(&mut self, today: i64) {
+        let entries_before = self.entries.len();
+        self.entries.retain(|_, entry| today - entry.last_used <= MAX_UNUSED_DAYS);
+        if self.entries.len() != entries_before {
+            self.dirty = true;
+        }
+    }
+
+    fn save(&mut self, today: i64) -> HandlerResult<()> {
+        let path = match store_path() {
+            Some(path) if self.dirty => path,
+            _ => return Ok(()),
+        };
+        self.prune(today);
+        // Write to a temporary file first so that the store is never left half-written.
+        let tmp_path = format!("{}.tmp", path);
+        fs::write(&tmp_path, serde_json::to_string(self)?)
+            .with_context(|| format!("Cannot write {}.", tmp_path))?;
+        fs::rename(&tmp_path, &path).with_context(|| format!("Cannot rename to {}.", path))?;
+        self.dirty = false;
+        Ok(())
+    }
+}
+
+fn store_path() -> Option<String> {
+    // Tests use in-memory store only.
+    if cfg!(test) {
+        return None;
+    }
+    Some(env::var(STORE_PATH_VAR).unwrap_or_else(|_| DEFAULT_STORE_PATH.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sequence() {
+        let mut store = SequenceStore::default();
+        assert_eq!(store.sequence("a", 1, 100, 0), 0);
+        assert_eq!(store.sequence("a", 1, 100, 0), 0);
+        assert_eq!(store.sequence("b", 1, 100, 0), 0);
+        assert_eq!(store.sequence("a", 2, 100, 0), 1);
+        assert_eq!(store.sequence("a", 2, 100, 0), 1);
+        assert_eq!(store.sequence("a", 1, 100, 0), 2);
+        assert_eq!(store.sequence("b", 1, 100, 0), 0);
+        assert!(store.dirty);
+    }
+
+    #[test]
+    fn test_sequence_floor() {
+        let mut store = SequenceStore::default();
+        assert_eq!(store.sequence("a", 1, 100, 1000), 1000);
+        // unchanged entries keep their sequence
+        assert_eq!(store.sequence("a", 1, 100, 1010), 1000);
+        assert_eq!(store.sequence("a", 2, 100, 1010), 1010);
+        // more changes within one floor step still increase the sequence
+        assert_eq!(store.sequence("a", 3, 100, 1010), 1011);
+        assert_eq!(store.sequence("a", 4, 100, 1010), 1012);
+
+        // a lost store starts above anything served before
+        let mut store = SequenceStore::default();
+        assert_eq!(store.sequence("a", 4, 100, 1020), 1020);
+    }
+
+    #[test]
+    fn test_serialization() {
+        let mut store = SequenceStore::default();
+        store.sequence("a", 1, 100, 0);
+        store.sequence("a", 2, 100, 0);
+
+        let json = serde_json::to_string(&store).unwrap();
+        let mut loaded: SequenceStore = serde_json::from_str(&json).unwrap();
+        assert!(!loaded.dirty);
+        assert_eq!(loaded.sequence("a", 2, 100, 0), 1);
+        assert!(!loaded.dirty);
+        assert_eq!(loaded.sequence("a", 3, 100, 0), 2);
+    }
+
+    #[test]
+    fn test_prune() {
+        let mut store = SequenceStore::default();
+        store.sequence("a", 1, 100, 0);
+        store.sequence("b", 1, 100, 0);
+        store.sequence("b", 1, 150, 0);
+
+        store.dirty = false;
+        store.prune(190);
+        assert_eq!(store.entries.len(), 2);
+        assert!(!store.dirty);
+
+        store.prune(191);
+        assert_eq!(store.entries.len(), 1);
+        assert!(store.entries.contains_key("b"));
+        assert!(store.dirty);
+    }
+
+    #[test]
+    fn test_thread_local_store() {
+        assert_eq!(get("thread-local", 1), 0);
+        assert_eq!(get("thread-local", 2), 1);
+        let in_other_thread = std::thread::spawn(|| get("thread-local", 3)).join().unwrap();
+        assert_eq!(in_other_thread, 0);
+    }
+}
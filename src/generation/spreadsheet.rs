@@ -0,0 +1,71 @@
+//! CSV export (RFC 4180) of schedules, one row per schedule.
+
+use super::ical::{is_all_day, ScheduleHelper};
+use super::Schedule;
+use crate::calendar::CalendarRequest;
+use csv::{Terminator, WriterBuilder};
+
+/// Byte order mark that makes Excel recognise UTF-8.
+const UTF8_BOM: &str = "\u{feff}";
+const HEADER: [&str; 14] = [
+    "start",
+    "end",
+    "all_day",
+    "event",
+    "categories",
+    "venue",
+    "city",
+    "country",
+    "latitude",
+    "longitude",
+    "pricing",
+    "currency",
+    "cancelled",
+    "url",
+];
+
+/// Render `schedules` as CSV. Times are local in the requested timezone, all-day schedules have
+/// dates only, with inclusive end.
+pub(super) fn render(schedules: &[Schedule], cal_req: &CalendarRequest) -> String {
+    let tz = cal_req.tz;
+    let mut writer = WriterBuilder::new().terminator(Terminator::CRLF).from_writer(Vec::new());
+    let expect_msg = "Writing CSV to Vec should not fail.";
+    writer.write_record(HEADER).expect(expect_msg);
+    for schedule in schedules {
+        let (start, end) = if is_all_day(schedule) {
+            (schedule.start_date(tz).to_string(), schedule.last_date(tz).to_string())
+        } else {
+            let format = "%Y-%m-%d %H:%M:%S";
+            let start = schedule.start.with_timezone(&tz).format(format).to_string();
+            (start, schedule.end.with_timezone(&tz).format(format).to_string())
+        };
+        let categories: Vec<&str> =
+            schedule.event.categories.values().map(|c| c.name.as_str()).collect();
+        let venue = &schedule.venue;
+        writer
+            .write_record([
+                &start,
+                &end,
+                &is_all_day(schedule).to_string(),
+                &schedule.event.name,
+                &categories.join(", "),
+                &venue.name,
+                &venue.city,
+                &venue.locality.country.name,
+                &venue.latitude.to_string(),
+                &venue.longitude.to_string(),
+                &schedule.pricing,
+                &schedule.currency,
+                &schedule.cancelled.to_string(),
+                &schedule.url,
+            ])
+            .expect(expect_msg);
+    }
+    let bytes = writer.into_inner().expect(expect_msg);
+    let csv = String::from_utf8(bytes).expect("CSV of Strings should be valid UTF-8.");
+    if cal_req.csv_bom {
+        format!("{}{}", UTF8_BOM, csv)
+    } else {
+        csv
+    }
+}
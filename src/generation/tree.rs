@@ -0,0 +1,325 @@
+//! Generic tree of iCalendar components, parsed back from our own iCalendar output. Serves as the
+//! source for alternative representations of the calendar, so that they carry exactly the same
+//! information as the iCalendar one.
+
+/// Calendar component (VCALENDAR, VEVENT, VALARM...) with its properties and subcomponents.
+#[derive(Debug, PartialEq)]
+pub(super) struct Component {
+    pub name: String,
+    pub properties: Vec<Property>,
+    pub components: Vec<Component>,
+}
+
+/// Calendar property, `value` is as it appears in iCalendar (i.e. escaped).
+#[derive(Debug, PartialEq)]
+pub(super) struct Property {
+    pub name: String,
+    pub params: Vec<(String, String)>,
+    pub value: String,
+}
+
+/// Value data types (RFC 5545 section 3.3) that our calendars use.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(super) enum ValueType {
+    Date,
+    DateTime,
+    Duration,
+    Float,
+    Integer,
+    Recur,
+    Text,
+    Uri,
+}
+
+impl ValueType {
+    /// Lowercase name of the type, as used by jCal and xCal.
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Date => "date",
+            Self::DateTime => "date-time",
+            Self::Duration => "duration",
+            Self::Float => "float",
+            Self::Integer => "integer",
+            Self::Recur => "recur",
+            Self::Text => "text",
+            Self::Uri => "uri",
+        }
+    }
+
+    fn from_param(value: &str) -> Option<Self> {
+        match value.to_uppercase().as_str() {
+            "DATE" => Some(Self::Date),
+            "DATE-TIME" => Some(Self::DateTime),
+            "DURATION" => Some(Self::Duration),
+            "FLOAT" => Some(Self::Float),
+            "INTEGER" => Some(Self::Integer),
+            "RECUR" => Some(Self::Recur),
+            "TEXT" => Some(Self::Text),
+            "URI" => Some(Self::Uri),
+            _ => None,
+        }
+    }
+
+    /// Type of property `name` when it has no VALUE parameter.
+    fn default_for(name: &str) -> Self {
+        match name {
+            "COMPLETED" | "CREATED" | "DTEND" | "DTSTAMP" | "DTSTART" | "DUE" | "EXDATE"
+            | "LAST-MODIFIED" | "RDATE" | "RECURRENCE-ID" => Self::DateTime,
+            "DURATION" | "TRIGGER" => Self::Duration,
+            "GEO" => Self::Float,
+            "PERCENT-COMPLETE" | "PRIORITY" | "REPEAT" | "SEQUENCE" => Self::Integer,
+            "EXRULE" | "RRULE" => Self::Recur,
+            "SOURCE" | "TZURL" | "URL" => Self::Uri,
+            _ => Self::Text,
+        }
+    }
+}
+
+impl Property {
+    pub fn value_type(&self) -> ValueType {
+        self.params
+            .iter()
+            .find(|(name, _)| name == "VALUE")
+            .and_then(|(_, value)| ValueType::from_param(value))
+            .unwrap_or_else(|| ValueType::default_for(&self.name))
+    }
+
+    /// Parameters other than VALUE, which is represented by value type instead.
+    pub fn params(&self) -> impl Iterator<Item = &(String, String)> {
+        self.params.iter().filter(|(name, _)| name != "VALUE")
+    }
+
+    /// Whether the value is structured, i.e. its `values()` are parts of one compound value.
+    pub fn is_structured(&self) -> bool {
+        self.name == "GEO"
+    }
+
+    /// Value(s) of the property in the format shared by jCal and xCal: unescaped text, dates and
+    /// times in ISO 8601 extended format.
+    pub fn values(&self) -> Vec<String> {
+        let value_type = self.value_type();
+        let separator = if self.is_structured() { ';' } else { ',' };
+        let parts = match (value_type, self.name.as_str()) {
+            (ValueType::Text, "CATEGORIES") | (ValueType::Text, "RESOURCES") => {
+                split_unescaped(&self.value, separator)
+            }
+            (ValueType::Text, _) | (ValueType::Recur, _) => vec![self.value.as_str()],
+            _ => self.value.split(separator).collect(),
+        };
+        parts.into_iter().map(|part| convert_value(part, value_type)).collect()
+    }
+}
+
+/// Split recurrence rule `value`, e.g. "FREQ=WEEKLY;BYDAY=MO,TU", into lowercase rule parts and
+/// their values, as used by jCal and xCal.
+pub(super) fn recur_parts(value: &str) -> Vec<(String, Vec<&str>)> {
+    value
+        .split(';')
+        .filter_map(|part| {
+            let mut name_values = part.splitn(2, '=');
+            let name = name_values.next()?.to_lowercase();
+            Some((name, name_values.next()?.split(',').collect()))
+        })
+        .collect()
+}
+
+/// Representation-independent content of a component: values are unescaped and typed. Used to
+/// prove equivalence of calendar formats.
+#[cfg(test)]
+#[derive(Debug, PartialEq)]
+pub(super) struct SemanticComponent {
+    pub name: String,
+    pub properties: Vec<SemanticProperty>,
+    pub components: Vec<SemanticComponent>,
+}
+
+#[cfg(test)]
+#[derive(Debug, PartialEq)]
+pub(super) struct SemanticProperty {
+    pub name: String,
+    pub params: Vec<(String, String)>,
+    pub value_type: ValueType,
+    pub values: Vec<String>,
+}
+
+#[cfg(test)]
+impl Component {
+    pub fn semantic(&self) -> SemanticComponent {
+        SemanticComponent {
+            name: self.name.clone(),
+            properties: self.properties.iter().map(Property::semantic).collect(),
+            components: self.components.iter().map(Self::semantic).collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+impl Property {
+    fn semantic(&self) -> SemanticProperty {
+        let value_type = self.value_type();
+        let mut values = self.values();
+        if value_type == ValueType::Recur {
+            // order of rule parts is not significant
+            for value in &mut values {
+                let mut parts: Vec<&str> = value.split(';').collect();
+                parts.sort_unstable();
+                *value = parts.join(";");
+            }
+        }
+        SemanticProperty {
+            name: self.name.clone(),
+            params: self.params().cloned().collect(),
+            value_type,
+            values,
+        }
+    }
+}
+
+/// Parse iCalendar `ical` produced by ical::render_calendar() into a tree. Panics on malformed
+/// input as we only parse our own output.
+pub(super) fn parse(ical: &str) -> Component {
+    let unfolded = ical.replace("\r\n ", "").replace("\r\n\t", "");
+    let mut stack: Vec<Component> = Vec::new();
+    let mut root = None;
+    for line in unfolded.split("\r\n").filter(|line| !line.is_empty()) {
+        let property = parse_line(line);
+        match property.name.as_str() {
+            "BEGIN" => stack.push(Component {
+                name: property.value,
+                properties: Vec::new(),
+                components: Vec::new(),
+            }),
+            "END" => {
+                let component = stack.pop().expect("END without BEGIN.");
+                assert_eq!(component.name, property.value, "Mismatched END.");
+                match stack.last_mut() {
+                    Some(parent) => parent.components.push(component),
+                    None => root = Some(component),
+                }
+            }
+            _ => stack.last_mut().expect("Property outside component.").properties.push(property),
+        }
+    }
+    assert!(stack.is_empty(), "Unterminated component.");
+    root.expect("No component in iCalendar.")
+}
+
+fn parse_line(line: &str) -> Property {
+    // parameter values may be quoted and contain ':', which is not the case of our output
+    let colon_index = line.find(':').unwrap_or_else(|| panic!("Malformed line {}", line));
+    let mut name_params = line[..colon_index].split(';');
+    let name = name_params.next().unwrap_or_default().to_uppercase();
+    let params = name_params
+        .map(|param| {
+            let mut parts = param.splitn(2, '=');
+            let param_name = parts.next().unwrap_or_default().to_uppercase();
+            let param_value = parts.next().unwrap_or_default().trim_matches('"');
+            (param_name, param_value.to_string())
+        })
+        .collect();
+    Property { name, params, value: line[colon_index + 1..].to_string() }
+}
+
+/// Split escaped text `value` on `separator`s not preceded by a backslash.
+fn split_unescaped(value: &str, separator: char) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let (mut start, mut escaped) = (0, false);
+    for (index, c) in value.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            _ if c == separator => {
+                parts.push(&value[start..index]);
+                start = index + c.len_utf8();
+            }
+            _ => (),
+        }
+    }
+    parts.push(&value[start..]);
+    parts
+}
+
+fn convert_value(value: &str, value_type: ValueType) -> String {
+    match value_type {
+        // 20200302 -> 2020-03-02
+        ValueType::Date if value.len() == 8 => {
+            format!("{}-{}-{}", &value[..4], &value[4..6], &value[6..])
+        }
+        // 20200302T190000Z -> 2020-03-02T19:00:00Z
+        ValueType::DateTime if value.len() >= 15 => format!(
+            "{}T{}:{}:{}",
+            convert_value(&value[..8], ValueType::Date),
+            &value[9..11],
+            &value[11..13],
+            &value[13..]
+        ),
+        ValueType::Text => unescape_text(value),
+        _ => value.to_string(),
+    }
+}
+
+fn unescape_text(value: &str) -> String {
+    let mut result = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') | Some('N') => result.push('\n'),
+            Some(escaped) => result.push(escaped),
+            None => result.push('\\'),
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let calendar = parse(
+            "BEGIN:VCALENDAR\r\nVERSION:2.0\r\nBEGIN:VEVENT\r\nSUMMARY:Long \r\n summary\r\n\
+             BEGIN:VALARM\r\nTRIGGER;RELATED=END:-P1D\r\nEND:VALARM\r\nEND:VEVENT\r\n\
+             END:VCALENDAR\r\n",
+        );
+        assert_eq!(calendar.name, "VCALENDAR");
+        assert_eq!(calendar.properties.len(), 1);
+        let event = &calendar.components[0];
+        assert_eq!(event.properties[0].value, "Long summary");
+        let trigger = &event.components[0].properties[0];
+        assert_eq!(trigger.params, vec![("RELATED".to_string(), "END".to_string())]);
+        assert_eq!(trigger.value_type(), ValueType::Duration);
+    }
+
+    #[test]
+    fn test_values() {
+        let values = |line: &str| {
+            let property = parse_line(line);
+            (property.value_type(), property.values())
+        };
+        assert_eq!(
+            values("DTSTART;VALUE=DATE:20160101"),
+            (ValueType::Date, vec!["2016-01-01".to_string()])
+        );
+        assert_eq!(
+            values("DTSTAMP:20191006T003433Z"),
+            (ValueType::DateTime, vec!["2019-10-06T00:34:33Z".to_string()])
+        );
+        assert_eq!(
+            values("GEO:52.5063;13.4436"),
+            (ValueType::Float, vec!["52.5063".to_string(), "13.4436".to_string()])
+        );
+        assert_eq!(
+            values("CATEGORIES:Concerts,A\\, B"),
+            (ValueType::Text, vec!["Concerts".to_string(), "A, B".to_string()])
+        );
+        assert_eq!(
+            values("DESCRIPTION:a\\nb\\; c\\, d\\\\"),
+            (ValueType::Text, vec!["a\nb; c, d\\".to_string()])
+        );
+    }
+}
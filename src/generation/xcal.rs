@@ -0,0 +1,106 @@
+//! Serialisation of calendars to xCal, the XML format for iCalendar (RFC 6321).
+
+use super::escape_xml;
+use super::tree::{recur_parts, Component, Property, ValueType};
+use std::fmt::Write;
+
+const XCAL_NAMESPACE: &str = "urn:ietf:params:xml:ns:icalendar-2.0";
+/// Names of the parts of structured values, only GEO is used by us.
+const GEO_PARTS: [&str; 2] = ["latitude", "longitude"];
+
+pub(super) fn render(calendar: &Component) -> String {
+    let mut output = String::new();
+    let expect_msg = "Writing to String should not fail.";
+    writeln!(output, "<?xml version=\"1.0\" encoding=\"utf-8\"?>").expect(expect_msg);
+    write!(output, "<icalendar xmlns=\"{}\">", XCAL_NAMESPACE).expect(expect_msg);
+    write_component(&mut output, calendar).expect(expect_msg);
+    writeln!(output, "</icalendar>").expect(expect_msg);
+    output
+}
+
+fn write_component(output: &mut String, component: &Component) -> std::fmt::Result {
+    let name = component.name.to_lowercase();
+    write!(output, "<{}>", name)?;
+    if !component.properties.is_empty() {
+        write!(output, "<properties>")?;
+        for property in component.properties.iter() {
+            write_property(output, property)?;
+        }
+        write!(output, "</properties>")?;
+    }
+    if !component.components.is_empty() {
+        write!(output, "<components>")?;
+        for subcomponent in component.components.iter() {
+            write_component(output, subcomponent)?;
+        }
+        write!(output, "</components>")?;
+    }
+    write!(output, "</{}>", name)
+}
+
+fn write_property(output: &mut String, property: &Property) -> std::fmt::Result {
+    let name = property.name.to_lowercase();
+    write!(output, "<{}>", name)?;
+    let mut params = property.params().peekable();
+    if params.peek().is_some() {
+        write!(output, "<parameters>")?;
+        for (param_name, param_value) in params {
+            let param_name = param_name.to_lowercase();
+            write!(output, "<{0}><text>{1}</text></{0}>", param_name, escape_xml(param_value))?;
+        }
+        write!(output, "</parameters>")?;
+    }
+    let value_type = property.value_type().name();
+    let values = property.values();
+    if property.is_structured() {
+        for (part, value) in GEO_PARTS.iter().zip(values.iter()) {
+            write!(output, "<{0}>{1}</{0}>", part, escape_xml(value))?;
+        }
+    } else if property.value_type() == ValueType::Recur {
+        for value in values.iter() {
+            write!(output, "<{}>", value_type)?;
+            for (part, part_values) in recur_parts(value) {
+                for part_value in part_values {
+                    write!(output, "<{0}>{1}</{0}>", part, escape_xml(part_value))?;
+                }
+            }
+            write!(output, "</{}>", value_type)?;
+        }
+    } else {
+        for value in values.iter() {
+            write!(output, "<{0}>{1}</{0}>", value_type, escape_xml(value))?;
+        }
+    }
+    write!(output, "</{}>", name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tree;
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let calendar = tree::parse(
+            "BEGIN:VCALENDAR\r\nVERSION:2.0\r\nBEGIN:VEVENT\r\nDTSTART;VALUE=DATE:20160101\r\n\
+             GEO:52.5063;13.4436\r\nCATEGORIES:A&B,C\r\nSUMMARY:<b>\\, c\r\nRRULE:FREQ=DAILY;COUNT=3\r\n\
+             BEGIN:VALARM\r\n\
+             TRIGGER;RELATED=END:-P1D\r\nEND:VALARM\r\nEND:VEVENT\r\nEND:VCALENDAR\r\n",
+        );
+        assert_eq!(
+            render(&calendar),
+            "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n\
+             <icalendar xmlns=\"urn:ietf:params:xml:ns:icalendar-2.0\"><vcalendar><properties>\
+             <version><text>2.0</text></version></properties><components><vevent><properties>\
+             <dtstart><date>2016-01-01</date></dtstart>\
+             <geo><latitude>52.5063</latitude><longitude>13.4436</longitude></geo>\
+             <categories><text>A&amp;B</text><text>C</text></categories>\
+             <summary><text>&lt;b&gt;, c</text></summary>\
+             <rrule><recur><freq>DAILY</freq><count>3</count></recur></rrule>\
+             </properties><components><valarm>\
+             <properties><trigger><parameters><related><text>END</text></related></parameters>\
+             <duration>-P1D</duration></trigger></properties></valarm></components></vevent>\
+             </components></vcalendar></icalendar>\n"
+        );
+    }
+}
@@ -0,0 +1,188 @@
+use once_cell::sync::Lazy;
+use rocket::FromFormValue;
+use serde::Deserialize;
+use std::collections::HashMap;
+
+/// Languages supported both by GoOut API and by our message catalogs.
+#[derive(Clone, Copy, Debug, Eq, FromFormValue, Hash, PartialEq)]
+pub(in crate) enum Language {
+    Cs,
+    De,
+    En,
+    Et,
+    Fi,
+    Pl,
+}
+
+/// Plural categories as defined by Unicode CLDR. Only integer counts are supported.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum PluralCategory {
+    One,
+    Few,
+    Many,
+    Other,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Message {
+    Simple(String),
+    Plural(HashMap<PluralCategory, String>),
+}
+
+type Catalog = HashMap<String, Message>;
+
+const COUNT_PLACEHOLDER: &str = "{count}";
+
+const ALL_LANGUAGES: [Language; 6] =
+    [Language::Cs, Language::De, Language::En, Language::Et, Language::Fi, Language::Pl];
+
+static CATALOGS: Lazy<HashMap<Language, Catalog>> = Lazy::new(|| {
+    ALL_LANGUAGES
+        .iter()
+        .map(|&language| {
+            let catalog = serde_json::from_str(language.catalog_source()).unwrap_or_else(|e| {
+                panic!("Invalid message catalog for {}: {}", language.code(), e)
+            });
+            (language, catalog)
+        })
+        .collect()
+});
+
+impl Language {
+    /// ISO 639-1 code of the language, as used by GoOut.
+    pub const fn code(self) -> &'static str {
+        match self {
+            Self::Cs => "cs",
+            Self::De => "de",
+            Self::En => "en",
+            Self::Et => "et",
+            Self::Fi => "fi",
+            Self::Pl => "pl",
+        }
+    }
+
+    /// Translate a simple message identified by `key`.
+    pub fn tr(self, key: &str) -> &'static str {
+        match self.message(key) {
+            Message::Simple(text) => text,
+            Message::Plural(_) => panic!("Message {} needs a count, use tr_count().", key),
+        }
+    }
+
+    /// Translate a message identified by `key` in a plural form appropriate for `count`.
+    /// Placeholder `{count}` in the message is replaced by the count.
+    pub fn tr_count(self, key: &str, count: usize) -> String {
+        let forms = match self.message(key) {
+            Message::Plural(forms) => forms,
+            Message::Simple(_) => panic!("Message {} has no plural forms, use tr().", key),
+        };
+        let category = self.plural_category(count);
+        let text = forms
+            .get(&category)
+            .unwrap_or_else(|| panic!("Message {} lacks {:?} form.", key, category));
+        text.replace(COUNT_PLACEHOLDER, &count.to_string())
+    }
+
+    fn message(self, key: &str) -> &'static Message {
+        // Fall back to English for messages that are not (yet) translated.
+        CATALOGS[&self]
+            .get(key)
+            .or_else(|| CATALOGS[&Self::En].get(key))
+            .unwrap_or_else(|| panic!("Unknown message {}.", key))
+    }
+
+    fn plural_category(self, count: usize) -> PluralCategory {
+        use PluralCategory::*;
+        match self {
+            Self::De | Self::En | Self::Et | Self::Fi => match count {
+                1 => One,
+                _ => Other,
+            },
+            Self::Cs => match count {
+                1 => One,
+                2..=4 => Few,
+                _ => Other,
+            },
+            Self::Pl => match (count, count % 10, count % 100) {
+                (1, _, _) => One,
+                (_, 2..=4, last_two) if !(12..=14).contains(&last_two) => Few,
+                _ => Many,
+            },
+        }
+    }
+
+    const fn catalog_source(self) -> &'static str {
+        match self {
+            Self::Cs => include_str!("../resources/l10n/cs.json"),
+            Self::De => include_str!("../resources/l10n/de.json"),
+            Self::En => include_str!("../resources/l10n/en.json"),
+            Self::Et => include_str!("../resources/l10n/et.json"),
+            Self::Fi => include_str!("../resources/l10n/fi.json"),
+            Self::Pl => include_str!("../resources/l10n/pl.json"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use PluralCategory::*;
+
+    #[test]
+    fn test_catalogs_complete() {
+        for &language in ALL_LANGUAGES.iter() {
+            let catalog = &CATALOGS[&language];
+            for (key, english_message) in CATALOGS[&Language::En].iter() {
+                let message = catalog
+                    .get(key)
+                    .unwrap_or_else(|| panic!("{} missing in {} catalog", key, language.code()));
+                match (message, english_message) {
+                    (Message::Simple(_), Message::Simple(_)) => (),
+                    (Message::Plural(forms), Message::Plural(_)) => {
+                        for count in 0..200 {
+                            let category = language.plural_category(count);
+                            assert!(forms.contains_key(&category), "{} {:?}", key, category);
+                        }
+                    }
+                    _ => panic!("{} has different kind in {} catalog", key, language.code()),
+                }
+            }
+            assert_eq!(catalog.len(), CATALOGS[&Language::En].len(), "{}", language.code());
+        }
+    }
+
+    #[test]
+    fn test_plural_category() {
+        let categories = |language: Language| {
+            [0, 1, 2, 4, 5, 11, 12, 22, 25, 112, 122]
+                .iter()
+                .map(|&count| language.plural_category(count))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            categories(Language::En),
+            vec![Other, One, Other, Other, Other, Other, Other, Other, Other, Other, Other]
+        );
+        assert_eq!(
+            categories(Language::Cs),
+            vec![Other, One, Few, Few, Other, Other, Other, Other, Other, Other, Other]
+        );
+        assert_eq!(
+            categories(Language::Pl),
+            vec![Many, One, Few, Few, Many, Many, Many, Few, Many, Many, Few]
+        );
+    }
+
+    #[test]
+    fn test_tr() {
+        assert_eq!(Language::En.tr("cancelled"), "Cancelled: ");
+        assert_eq!(Language::Cs.tr("phase-end"), "Konec: ");
+        assert_eq!(Language::En.tr_count("count-begin", 1), "1 beginning");
+        assert_eq!(Language::En.tr_count("count-begin", 2), "2 beginnings");
+        assert_eq!(Language::Cs.tr_count("count-end", 3), "3 konce");
+        assert_eq!(Language::Cs.tr_count("count-end", 5), "5 konců");
+        assert_eq!(Language::Pl.tr_count("count-begin", 22), "22 początki");
+    }
+}
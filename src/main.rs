@@ -3,9 +3,12 @@
 
 use rocket::{routes, Rocket};
 
+mod api;
 mod calendar;
+mod digest;
 mod error;
 mod generation;
+mod l10n;
 mod static_pages;
 
 fn main() {
@@ -13,5 +16,17 @@ fn main() {
 }
 
 fn rocket() -> Rocket {
-    rocket::ignite().mount("/", routes![static_pages::index, static_pages::script, calendar::serve])
+    rocket::ignite().mount(
+        "/",
+        routes![
+            static_pages::index,
+            static_pages::script,
+            api::schedules,
+            calendar::serve,
+            calendar::serve_rss,
+            calendar::serve_atom,
+            calendar::agenda,
+            digest::digest
+        ],
+    )
 }